@@ -0,0 +1,2 @@
+USE somsiad;
+ALTER TABLE `markers` ADD FULLTEXT KEY `markers_search` (`title`, `description`);
@@ -0,0 +1,4 @@
+USE somsiad;
+ALTER TABLE `markers`
+ADD `archived_at` TIMESTAMP NULL DEFAULT NULL AFTER `end_time`,
+ADD KEY `markers_archived` (`archived_at`);
@@ -0,0 +1,4 @@
+USE somsiad;
+ALTER TABLE `markers`
+ADD `recurrence` JSON NULL DEFAULT NULL AFTER `archived_at`,
+ADD `series_end` TIMESTAMP NULL DEFAULT NULL AFTER `recurrence`;
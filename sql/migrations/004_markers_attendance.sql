@@ -0,0 +1,14 @@
+USE somsiad;
+ALTER TABLE `markers`
+ADD `capacity` INT UNSIGNED NULL DEFAULT NULL AFTER `series_end`;
+
+CREATE TABLE `attendance` (
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`status` ENUM("going","interested") NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`marker_id`, `user_id`),
+KEY `attendance_user` (`user_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `attendance` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `attendance` add foreign key (`user_id`) references users (`id`) on delete cascade;
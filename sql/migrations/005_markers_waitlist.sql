@@ -0,0 +1,12 @@
+USE somsiad;
+CREATE TABLE `waitlist` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+UNIQUE KEY `waitlist_marker_user` (`marker_id`, `user_id`),
+KEY `waitlist_user` (`user_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `waitlist` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `waitlist` add foreign key (`user_id`) references users (`id`) on delete cascade;
@@ -0,0 +1,14 @@
+USE somsiad;
+CREATE TABLE `comments` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`parent_id` INT UNSIGNED NULL DEFAULT NULL,
+`user_id` INT NOT NULL,
+`body` TEXT NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `comments_marker` (`marker_id`, `parent_id`, `add_time`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `comments` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `comments` add foreign key (`parent_id`) references comments (`id`) on delete cascade;
+alter table `comments` add foreign key (`user_id`) references users (`id`) on delete cascade;
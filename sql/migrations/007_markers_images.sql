@@ -0,0 +1,10 @@
+USE somsiad;
+CREATE TABLE `marker_images` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`file_name` VARCHAR(64) NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `marker_images_marker` (`marker_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `marker_images` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
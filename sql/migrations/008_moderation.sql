@@ -0,0 +1,33 @@
+USE somsiad;
+ALTER TABLE `users`
+ADD `moderator` BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE `markers`
+ADD `hidden_at` TIMESTAMP NULL DEFAULT NULL AFTER `archived_at`;
+
+CREATE TABLE `reports` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`reason` VARCHAR(500) NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+`resolved_at` TIMESTAMP NULL DEFAULT NULL,
+PRIMARY KEY (`id`),
+UNIQUE KEY `reports_marker_user` (`marker_id`, `user_id`),
+KEY `reports_resolved` (`resolved_at`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `reports` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `reports` add foreign key (`user_id`) references users (`id`) on delete cascade;
+
+CREATE TABLE `warnings` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`user_id` INT NOT NULL,
+`moderator_id` INT NOT NULL,
+`marker_id` INT UNSIGNED NULL DEFAULT NULL,
+`reason` VARCHAR(500) NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `warnings_user` (`user_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `warnings` add foreign key (`user_id`) references users (`id`) on delete cascade;
+alter table `warnings` add foreign key (`moderator_id`) references users (`id`) on delete cascade;
+alter table `warnings` add foreign key (`marker_id`) references markers (`id`) on delete set null;
@@ -0,0 +1,31 @@
+USE somsiad;
+CREATE TABLE `reputation_events` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`user_id` INT NOT NULL,
+`reason` ENUM("help","upvote","penalty") NOT NULL,
+`points` SMALLINT NOT NULL,
+`marker_id` INT UNSIGNED NULL DEFAULT NULL,
+`source_user_id` INT NULL DEFAULT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `reputation_events_user` (`user_id`),
+KEY `reputation_events_marker` (`marker_id`, `source_user_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `reputation_events` add foreign key (`user_id`) references users (`id`) on delete cascade;
+alter table `reputation_events` add foreign key (`marker_id`) references markers (`id`) on delete set null;
+alter table `reputation_events` add foreign key (`source_user_id`) references users (`id`) on delete set null;
+
+CREATE TABLE `marker_votes` (
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`marker_id`, `user_id`),
+KEY `marker_votes_user` (`user_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `marker_votes` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `marker_votes` add foreign key (`user_id`) references users (`id`) on delete cascade;
+
+/* Reputation used to come from the client, nothing in the ledger backs it */
+ALTER TABLE `full_users_info`
+MODIFY `reputation` mediumint NOT NULL DEFAULT 0;
+UPDATE `full_users_info` SET `reputation` = 0;
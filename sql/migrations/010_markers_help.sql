@@ -0,0 +1,26 @@
+USE somsiad;
+CREATE TABLE `help_requests` (
+`marker_id` INT UNSIGNED NOT NULL,
+`status` ENUM("open","claimed","confirmed","done") NOT NULL,
+`helper_id` INT NULL DEFAULT NULL,
+`update_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+PRIMARY KEY (`marker_id`),
+KEY `help_requests_helper` (`helper_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `help_requests` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `help_requests` add foreign key (`helper_id`) references users (`id`) on delete set null;
+
+CREATE TABLE `help_transitions` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`helper_id` INT NULL DEFAULT NULL,
+`from_status` ENUM("open","claimed","confirmed","done") NOT NULL,
+`to_status` ENUM("open","claimed","confirmed","done") NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `help_transitions_marker` (`marker_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `help_transitions` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `help_transitions` add foreign key (`user_id`) references users (`id`) on delete cascade;
+alter table `help_transitions` add foreign key (`helper_id`) references users (`id`) on delete set null;
@@ -0,0 +1,39 @@
+USE somsiad;
+ALTER TABLE `users`
+ADD `admin` BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE `categories` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`slug` VARCHAR(50) NOT NULL UNIQUE,
+`name_pl` VARCHAR(100) NOT NULL,
+`name_en` VARCHAR(100) NOT NULL,
+`icon` VARCHAR(50) NULL DEFAULT NULL,
+`colour` CHAR(7) NULL DEFAULT NULL,
+`parent_id` INT UNSIGNED NULL DEFAULT NULL,
+`attendance` BOOLEAN NOT NULL DEFAULT FALSE,
+`help` BOOLEAN NOT NULL DEFAULT FALSE,
+PRIMARY KEY (`id`),
+KEY `categories_parent` (`parent_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `categories` add foreign key (`parent_id`) references categories (`id`);
+
+/* The former marker types, keeping their order */
+INSERT INTO `categories` (`id`, `slug`, `name_pl`, `name_en`, `attendance`, `help`) VALUES
+(1, "neighbor-help", "Pomoc sąsiedzka", "Neighbour help", FALSE, TRUE),
+(2, "happening", "Wydarzenie", "Happening", TRUE, FALSE),
+(3, "charity", "Akcja charytatywna", "Charity", FALSE, FALSE),
+(4, "mass-event", "Impreza masowa", "Mass event", TRUE, FALSE);
+
+ALTER TABLE `markers`
+ADD `category_id` INT UNSIGNED NULL DEFAULT NULL AFTER `type`;
+UPDATE `markers` SET `category_id` = CASE `type`
+    WHEN "A" THEN 1
+    WHEN "B" THEN 2
+    WHEN "C" THEN 3
+    WHEN "D" THEN 4
+END;
+ALTER TABLE `markers`
+MODIFY `category_id` INT UNSIGNED NOT NULL,
+ADD KEY `markers_category` (`category_id`),
+DROP COLUMN `type`;
+alter table `markers` add foreign key (`category_id`) references categories (`id`);
@@ -0,0 +1,12 @@
+USE somsiad;
+CREATE TABLE `contact_reveals` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `contact_reveals_user` (`user_id`, `add_time`),
+KEY `contact_reveals_marker` (`marker_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `contact_reveals` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `contact_reveals` add foreign key (`user_id`) references users (`id`) on delete cascade;
@@ -0,0 +1,4 @@
+USE somsiad;
+ALTER TABLE `markers`
+ADD `deleted_at` TIMESTAMP NULL DEFAULT NULL AFTER `hidden_at`,
+ADD KEY `markers_deleted` (`deleted_at`);
@@ -0,0 +1,5 @@
+USE somsiad;
+ALTER TABLE `markers`
+ADD `draft` BOOLEAN NOT NULL DEFAULT FALSE AFTER `deleted_at`,
+ADD `publish_at` TIMESTAMP NULL DEFAULT NULL AFTER `draft`,
+ADD KEY `markers_publish` (`draft`, `publish_at`);
@@ -0,0 +1,4 @@
+USE somsiad;
+/* Withdrawn upvotes are offset by a negative entry instead of being erased from the ledger */
+ALTER TABLE `reputation_events`
+MODIFY `reason` ENUM("help","upvote","unvote","penalty") NOT NULL;
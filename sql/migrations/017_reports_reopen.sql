@@ -0,0 +1,7 @@
+USE somsiad;
+/* Only open reports are unique, users may report a marker again once their report is resolved */
+ALTER TABLE `reports`
+ADD `open_marker_id` INT UNSIGNED AS (IF(`resolved_at` IS NULL, `marker_id`, NULL)) VIRTUAL,
+ADD KEY `reports_marker` (`marker_id`),
+ADD UNIQUE KEY `reports_open_marker_user` (`open_marker_id`, `user_id`),
+DROP INDEX `reports_marker_user`;
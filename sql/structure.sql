@@ -5,6 +5,8 @@ CREATE TABLE `users` (
  `email` varchar(255) NOT NULL UNIQUE,
  `name` varchar(255) NOT NULL UNIQUE,
  `password` varchar(60) NOT NULL,
+ `moderator` BOOLEAN NOT NULL DEFAULT FALSE,
+`admin` BOOLEAN NOT NULL DEFAULT FALSE,
   PRIMARY KEY (`id`)
 ) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
 
@@ -14,27 +16,215 @@ create table `full_users_info`(
 `surname` varchar(30) NOT NULL,
 `sex` ENUM('M','F','O') NOT NULL,
 `address` JSON NOT NULL,
-`reputation` mediumint NOT NULL,
+`reputation` mediumint NOT NULL DEFAULT 0,
  PRIMARY KEY (`id`)
 ) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
 alter table full_users_info add foreign key (id) references users (id) on delete cascade on update cascade;
 
+CREATE TABLE `categories` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`slug` VARCHAR(50) NOT NULL UNIQUE,
+`name_pl` VARCHAR(100) NOT NULL,
+`name_en` VARCHAR(100) NOT NULL,
+`icon` VARCHAR(50) NULL DEFAULT NULL,
+`colour` CHAR(7) NULL DEFAULT NULL,
+`parent_id` INT UNSIGNED NULL DEFAULT NULL,
+`attendance` BOOLEAN NOT NULL DEFAULT FALSE,
+`help` BOOLEAN NOT NULL DEFAULT FALSE,
+PRIMARY KEY (`id`),
+KEY `categories_parent` (`parent_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `categories` add foreign key (`parent_id`) references categories (`id`);
+INSERT INTO `categories` (`id`, `slug`, `name_pl`, `name_en`, `attendance`, `help`) VALUES
+(1, "neighbor-help", "Pomoc sąsiedzka", "Neighbour help", FALSE, TRUE),
+(2, "happening", "Wydarzenie", "Happening", TRUE, FALSE),
+(3, "charity", "Akcja charytatywna", "Charity", FALSE, FALSE),
+(4, "mass-event", "Impreza masowa", "Mass event", TRUE, FALSE);
+
 CREATE TABLE `markers` (
 `id` INT UNSIGNED NOT NULL AUTO_INCREMENT ,
 `latitude` double NOT NULL,
 `longitude` double NOT NULL,
 `title` VARCHAR(25) NOT NULL,
 `description` TEXT NOT NULL,
-`type` ENUM("A","B","C","D") NOT NULL,
+`category_id` INT UNSIGNED NOT NULL,
 `add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
 `start_time` TIMESTAMP NULL DEFAULT NULL,
 `end_time` TIMESTAMP NULL DEFAULT NULL,
+`archived_at` TIMESTAMP NULL DEFAULT NULL,
+`hidden_at` TIMESTAMP NULL DEFAULT NULL,
+`deleted_at` TIMESTAMP NULL DEFAULT NULL,
+`draft` BOOLEAN NOT NULL DEFAULT FALSE,
+`publish_at` TIMESTAMP NULL DEFAULT NULL,
+`recurrence` JSON NULL DEFAULT NULL,
+`series_end` TIMESTAMP NULL DEFAULT NULL,
+`capacity` INT UNSIGNED NULL DEFAULT NULL,
 `address` JSON NOT NULL,
 `contact_info` JSON NOT NULL,
 `user_id` INT NOT NULL,
-PRIMARY KEY (`ID`)
+PRIMARY KEY (`ID`),
+KEY `markers_archived` (`archived_at`),
+KEY `markers_deleted` (`deleted_at`),
+KEY `markers_publish` (`draft`, `publish_at`),
+KEY `markers_category` (`category_id`),
+FULLTEXT KEY `markers_search` (`title`, `description`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `markers` add foreign key (`user_id`) references users (`id`);
+alter table `markers` add foreign key (`category_id`) references categories (`id`);
+
+CREATE TABLE `attendance` (
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`status` ENUM("going","interested") NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`marker_id`, `user_id`),
+KEY `attendance_user` (`user_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `attendance` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `attendance` add foreign key (`user_id`) references users (`id`) on delete cascade;
+
+CREATE TABLE `waitlist` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+UNIQUE KEY `waitlist_marker_user` (`marker_id`, `user_id`),
+KEY `waitlist_user` (`user_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `waitlist` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `waitlist` add foreign key (`user_id`) references users (`id`) on delete cascade;
+
+CREATE TABLE `comments` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`parent_id` INT UNSIGNED NULL DEFAULT NULL,
+`user_id` INT NOT NULL,
+`body` TEXT NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `comments_marker` (`marker_id`, `parent_id`, `add_time`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `comments` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `comments` add foreign key (`parent_id`) references comments (`id`) on delete cascade;
+alter table `comments` add foreign key (`user_id`) references users (`id`) on delete cascade;
+
+CREATE TABLE `marker_images` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`file_name` VARCHAR(64) NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `marker_images_marker` (`marker_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `marker_images` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+
+CREATE TABLE `reports` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`reason` VARCHAR(500) NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+`resolved_at` TIMESTAMP NULL DEFAULT NULL,
+/* Only open reports are unique, users may report a marker again once their report is resolved */
+`open_marker_id` INT UNSIGNED AS (IF(`resolved_at` IS NULL, `marker_id`, NULL)) VIRTUAL,
+PRIMARY KEY (`id`),
+KEY `reports_marker` (`marker_id`),
+UNIQUE KEY `reports_open_marker_user` (`open_marker_id`, `user_id`),
+KEY `reports_resolved` (`resolved_at`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `reports` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `reports` add foreign key (`user_id`) references users (`id`) on delete cascade;
+
+CREATE TABLE `warnings` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`user_id` INT NOT NULL,
+`moderator_id` INT NOT NULL,
+`marker_id` INT UNSIGNED NULL DEFAULT NULL,
+`reason` VARCHAR(500) NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `warnings_user` (`user_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `warnings` add foreign key (`user_id`) references users (`id`) on delete cascade;
+alter table `warnings` add foreign key (`moderator_id`) references users (`id`) on delete cascade;
+alter table `warnings` add foreign key (`marker_id`) references markers (`id`) on delete set null;
+
+CREATE TABLE `reputation_events` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`user_id` INT NOT NULL,
+`reason` ENUM("help","upvote","unvote","penalty") NOT NULL,
+`points` SMALLINT NOT NULL,
+`marker_id` INT UNSIGNED NULL DEFAULT NULL,
+`source_user_id` INT NULL DEFAULT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `reputation_events_user` (`user_id`),
+KEY `reputation_events_marker` (`marker_id`, `source_user_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `reputation_events` add foreign key (`user_id`) references users (`id`) on delete cascade;
+alter table `reputation_events` add foreign key (`marker_id`) references markers (`id`) on delete set null;
+alter table `reputation_events` add foreign key (`source_user_id`) references users (`id`) on delete set null;
+
+CREATE TABLE `marker_votes` (
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`marker_id`, `user_id`),
+KEY `marker_votes_user` (`user_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `marker_votes` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `marker_votes` add foreign key (`user_id`) references users (`id`) on delete cascade;
+
+CREATE TABLE `help_requests` (
+`marker_id` INT UNSIGNED NOT NULL,
+`status` ENUM("open","claimed","confirmed","done") NOT NULL,
+`helper_id` INT NULL DEFAULT NULL,
+`update_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+PRIMARY KEY (`marker_id`),
+KEY `help_requests_helper` (`helper_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `help_requests` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `help_requests` add foreign key (`helper_id`) references users (`id`) on delete set null;
+
+CREATE TABLE `help_transitions` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`helper_id` INT NULL DEFAULT NULL,
+`from_status` ENUM("open","claimed","confirmed","done") NOT NULL,
+`to_status` ENUM("open","claimed","confirmed","done") NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `help_transitions_marker` (`marker_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `help_transitions` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `help_transitions` add foreign key (`user_id`) references users (`id`) on delete cascade;
+alter table `help_transitions` add foreign key (`helper_id`) references users (`id`) on delete set null;
+
+CREATE TABLE `contact_reveals` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `contact_reveals_user` (`user_id`, `add_time`),
+KEY `contact_reveals_marker` (`marker_id`)
+) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
+alter table `contact_reveals` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `contact_reveals` add foreign key (`user_id`) references users (`id`) on delete cascade;
+
+CREATE TABLE `marker_revisions` (
+`id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+`marker_id` INT UNSIGNED NOT NULL,
+`user_id` INT NOT NULL,
+`changes` JSON NOT NULL,
+`add_time` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY (`id`),
+KEY `marker_revisions_marker` (`marker_id`)
 ) ENGINE = InnoDB CHARSET=utf8mb4 COLLATE utf8mb4_polish_ci;
-alter table `markers` add foreign key (`user_id`) references users (`id`)
+alter table `marker_revisions` add foreign key (`marker_id`) references markers (`id`) on delete cascade;
+alter table `marker_revisions` add foreign key (`user_id`) references users (`id`) on delete cascade;
 
 /* Example address JSON:
 {
@@ -32,12 +32,16 @@ impl Fairing for CORS {
 
         response.set_header(Header::new(
             "Access-Control-Allow-Methods",
-            "POST, GET, DELETE, OPTIONS, PUT",
+            "POST, GET, DELETE, OPTIONS, PUT, PATCH",
         ));
         response.set_header(Header::new(
             "Access-Control-Allow-Headers",
             "Accept, Content-Type",
         ));
         response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        response.set_header(Header::new(
+            "Access-Control-Expose-Headers",
+            crate::markers::pagination::NEXT_CURSOR_HEADER,
+        ));
     }
 }
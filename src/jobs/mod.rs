@@ -0,0 +1,98 @@
+use rocket::tokio::{self, time};
+use rocket::{error, info};
+use sqlx::MySqlPool;
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::markers::archive::{archive_markers, DEFAULT_LIFETIME_DAYS};
+use crate::markers::drafts::publish_scheduled_markers;
+use crate::markers::trash::{purge_deleted_markers, DEFAULT_RETENTION_DAYS};
+
+/// How often background jobs run unless configured otherwise
+pub const DEFAULT_INTERVAL_SECONDS: u64 = 600;
+/// Scheduled markers should go public close to their time, so they are checked more often
+pub const DEFAULT_PUBLISH_INTERVAL_SECONDS: u64 = 60;
+/// Upper bound of periods between runs, longer ones would also risk overflowing the timer
+pub const MAX_INTERVAL_SECONDS: u64 = 86400;
+/// Upper bound of day counts in settings, far below where `chrono::Duration` overflows
+pub const MAX_DAYS: i64 = 36500;
+
+/// Reads an optional setting from the environment, falling back to the default when it is
+/// missing or malformed
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Reads how often a job runs, clamped to `1..=MAX_INTERVAL_SECONDS` as
+/// `tokio::time::interval` panics on a zero period
+pub fn env_period(name: &str, default: u64) -> Duration {
+    Duration::from_secs(env_or(name, default).clamp(1, MAX_INTERVAL_SECONDS))
+}
+
+/// Reads a number of days, clamped to `1..=MAX_DAYS`
+pub fn env_days(name: &str, default: i64) -> chrono::Duration {
+    chrono::Duration::days(env_or(name, default).clamp(1, MAX_DAYS))
+}
+
+/// Periodically archives markers that have ended.
+///
+/// Configured with `ARCHIVE_INTERVAL_SECONDS` and `MARKER_LIFETIME_DAYS`
+pub fn spawn_archiver(db: MySqlPool) {
+    let period = env_period("ARCHIVE_INTERVAL_SECONDS", DEFAULT_INTERVAL_SECONDS);
+    let lifetime = env_days("MARKER_LIFETIME_DAYS", DEFAULT_LIFETIME_DAYS);
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(period);
+        loop {
+            interval.tick().await;
+            match archive_markers(&db, lifetime).await {
+                Ok(0) => {}
+                Ok(archived) => info!("Archived {} markers", archived),
+                Err(e) => error!("Failed to archive markers: {}", e),
+            }
+        }
+    });
+}
+
+/// Periodically purges markers that stayed in the trash for too long.
+///
+/// Configured with `PURGE_INTERVAL_SECONDS` and `TRASH_RETENTION_DAYS`
+pub fn spawn_purger(db: MySqlPool) {
+    let period = env_period("PURGE_INTERVAL_SECONDS", DEFAULT_INTERVAL_SECONDS);
+    let retention = env_days("TRASH_RETENTION_DAYS", DEFAULT_RETENTION_DAYS);
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(period);
+        loop {
+            interval.tick().await;
+            match purge_deleted_markers(&db, retention).await {
+                Ok(0) => {}
+                Ok(purged) => info!("Purged {} deleted markers", purged),
+                Err(e) => error!("Failed to purge deleted markers: {}", e),
+            }
+        }
+    });
+}
+
+/// Periodically publishes drafts whose publication time has come.
+///
+/// Configured with `PUBLISH_INTERVAL_SECONDS`
+pub fn spawn_publisher(db: MySqlPool) {
+    let period = env_period("PUBLISH_INTERVAL_SECONDS", DEFAULT_PUBLISH_INTERVAL_SECONDS);
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(period);
+        loop {
+            interval.tick().await;
+            match publish_scheduled_markers(&db).await {
+                Ok(0) => {}
+                Ok(published) => info!("Published {} scheduled markers", published),
+                Err(e) => error!("Failed to publish scheduled markers: {}", e),
+            }
+        }
+    });
+}
@@ -1,15 +1,27 @@
 /* modules */
 pub mod fairings;
+pub mod jobs;
+pub mod mail;
 pub mod markers;
+pub mod moderation;
 pub mod routes;
+#[cfg(test)]
+mod testing;
 pub mod users;
 
-use rocket::{http::Status, outcome::IntoOutcome, request, Request};
+use rocket::{
+    error_,
+    http::Status,
+    outcome::{try_outcome, IntoOutcome},
+    request, Request,
+};
 /* Uses */
+use moderation::Role;
 pub use rocket::config::SecretKey;
 use rocket::request::FromRequest;
 pub use rocket::serde::json::Json;
 use serde::Serialize;
+use sqlx::MySqlPool;
 pub use validator::Validate;
 
 #[derive(Serialize, Debug)]
@@ -56,3 +68,42 @@ impl<'a> FromRequest<'a> for UserID {
             .into_outcome((Status::Unauthorized, ()))
     }
 }
+
+/// Id of the signed in user, as long as they have the given role
+async fn user_with_role(request: &Request<'_>, role: Role) -> request::Outcome<u32, ()> {
+    let user_id = try_outcome!(request.guard::<UserID>().await);
+    let db = match request.rocket().state::<MySqlPool>() {
+        Some(db) => db,
+        None => return request::Outcome::Failure((Status::InternalServerError, ())),
+    };
+    match moderation::has_role(db, user_id.0, role).await {
+        Ok(true) => request::Outcome::Success(user_id.0),
+        Ok(false) => request::Outcome::Failure((Status::Forbidden, ())),
+        Err(e) => {
+            error_!("Error while checking user rights: {}", e);
+            request::Outcome::Failure((Status::InternalServerError, ()))
+        }
+    }
+}
+
+/// Signed in user with moderator rights
+pub struct ModeratorID(u32);
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for ModeratorID {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'_>) -> request::Outcome<Self, Self::Error> {
+        user_with_role(request, Role::Moderator).await.map(Self)
+    }
+}
+
+/// Signed in user managing the site, e.g. its categories
+pub struct AdminID(u32);
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for AdminID {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'_>) -> request::Outcome<Self, Self::Error> {
+        user_with_role(request, Role::Admin).await.map(Self)
+    }
+}
@@ -0,0 +1,55 @@
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use rocket::{info, warn};
+use std::env;
+
+/// Sends notifications to users. Without `SMTP_HOST` set, messages are only logged
+pub struct Mailer {
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    /// Configured with `SMTP_HOST`, `SMTP_USERNAME`, `SMTP_PASSWORD` and `MAIL_FROM`
+    pub fn from_env() -> anyhow::Result<Self> {
+        let from = env::var("MAIL_FROM")
+            .unwrap_or_else(|_| "CoSięDzieje <noreply@cosiedzieje.mikut.dev>".to_string())
+            .parse()?;
+        let transport = match env::var("SMTP_HOST") {
+            Ok(host) => {
+                let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(&host)?;
+                if let (Ok(username), Ok(password)) =
+                    (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD"))
+                {
+                    builder = builder.credentials(Credentials::new(username, password));
+                }
+                Some(builder.build())
+            }
+            Err(_) => {
+                warn!("SMTP_HOST is not set, emails won't be sent");
+                None
+            }
+        };
+
+        Ok(Self { transport, from })
+    }
+
+    pub async fn send(&self, to: &str, subject: &str, body: String) -> anyhow::Result<()> {
+        let transport = match &self.transport {
+            Some(transport) => transport,
+            None => {
+                info!("Email to {} not sent: {}", to, subject);
+                return Ok(());
+            }
+        };
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse()?)
+            .subject(subject)
+            .body(body)?;
+        transport.send(message).await?;
+
+        Ok(())
+    }
+}
@@ -2,6 +2,9 @@ use dotenv::dotenv;
 use rocket::fs::relative;
 use rocket::fs::FileServer;
 use somsiad_api::fairings;
+use somsiad_api::jobs;
+use somsiad_api::mail::Mailer;
+use somsiad_api::markers::images::{PENDING_UPLOADS_DIR, UPLOADS_DIR, UPLOADS_URL};
 use somsiad_api::routes::*;
 use sqlx::pool::PoolOptions;
 use sqlx::MySql;
@@ -21,10 +24,20 @@ async fn main() -> Result<(), rocket::Error> {
         .await
         .expect("Failed to connect to db");
 
+    jobs::spawn_archiver(db.clone());
+    jobs::spawn_purger(db.clone());
+    jobs::spawn_publisher(db.clone());
+    let mailer = Mailer::from_env().expect("Failed to configure the mailer");
+    std::fs::create_dir_all(UPLOADS_DIR).expect("Failed to create the uploads directory");
+    std::fs::create_dir_all(PENDING_UPLOADS_DIR)
+        .expect("Failed to create the pending uploads directory");
+
     let _rocket = rocket::build()
         .attach(fairings::CORS)
         .manage(db)
+        .manage(mailer)
         .mount("/", FileServer::from(relative!("static")).rank(1))
+        .mount(UPLOADS_URL, routes![get_marker_image_file])
         .mount(
             "/api",
             routes![
@@ -35,14 +48,63 @@ async fn main() -> Result<(), rocket::Error> {
                 user_data,
                 is_logged,
                 get_markers,
+                get_marker,
                 add_marker,
                 remove_marker,
+                edit_marker,
                 get_user_markers,
+                get_user_trash,
+                restore_user_marker,
+                publish_user_marker,
                 get_markers_by_city,
                 get_markers_by_dist,
+                get_markers_by_phrase,
+                get_markers_in_bbox,
+                get_marker_clusters,
+                get_markers_geojson,
+                get_markers_in_bbox_geojson,
+                get_user_markers_geojson,
+                get_marker_ics,
+                get_markers_ics,
+                get_city_atom_feed,
+                get_city_rss_feed,
+                attend_marker,
+                leave_marker_attendance,
+                get_marker_attendees,
+                get_marker_contact,
+                get_marker_contact_views,
+                get_marker_history,
+                get_waitlist_position,
+                get_user_waitlist,
+                get_marker_comments,
+                add_marker_comment,
+                remove_comment,
+                add_marker_image,
+                remove_marker_image,
+                claim_help,
+                confirm_help,
+                finish_help,
+                release_help,
+                get_help_history,
+                upvote,
+                withdraw_upvote,
+                get_categories,
+                add_category,
+                edit_category,
+                remove_category,
+                report_marker,
+                get_report_queue,
+                hide_marker,
+                restore_marker,
+                moderator_remove_marker,
+                rollback_marker_revision,
+                warn_marker_author,
             ],
         )
-        .register("/", catchers![options_catcher, unauthorized_catcher])
+        .register(
+            "/",
+            catchers![options_catcher, unauthorized_catcher, forbidden_catcher],
+        )
         .launch()
         .await?;
 
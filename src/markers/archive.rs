@@ -0,0 +1,31 @@
+use chrono::{Duration, Utc};
+
+/// Lifetime of markers without an end time, counted from their start or, lacking that, from
+/// the moment they were added
+pub const DEFAULT_LIFETIME_DAYS: i64 = 30;
+
+/// Archives markers that have ended, returns how many of them were archived.
+/// Recurring markers are archived after their last occurrence, endless series never are.
+/// Drafts wait until they are published
+pub async fn archive_markers(
+    db: &sqlx::MySqlPool,
+    default_lifetime: Duration,
+) -> anyhow::Result<u64> {
+    let now = Utc::now();
+    let archived = sqlx::query!(
+        r#"
+        UPDATE markers SET archived_at = ?
+        WHERE archived_at IS NULL AND deleted_at IS NULL AND NOT draft
+        AND IF(recurrence IS NULL,
+            end_time < ? OR (end_time IS NULL AND COALESCE(start_time, add_time) < ?),
+            series_end < ?)
+        "#,
+        now,
+        now - default_lifetime,
+        now
+    )
+    .execute(db)
+    .await?;
+
+    Ok(archived.rows_affected())
+}
@@ -0,0 +1,486 @@
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use rocket::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Transaction};
+use std::fmt;
+
+use crate::mail::Mailer;
+
+#[derive(Debug)]
+pub enum AttendanceError {
+    NotFound,
+    NotAllowed,
+}
+
+impl fmt::Display for AttendanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "marker not found"),
+            Self::NotAllowed => write!(f, "marker category doesn't take attendance"),
+        }
+    }
+}
+
+impl std::error::Error for AttendanceError {}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum AttendanceStatus {
+    Going,
+    Interested,
+}
+
+#[derive(Deserialize)]
+pub struct AttendanceRequest {
+    status: AttendanceStatus,
+}
+
+/// Where the user ended up after signing up. People going to a full event land on its waitlist
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Signup {
+    Going,
+    Interested,
+    Waiting { position: i64 },
+}
+
+#[derive(Serialize)]
+pub struct WaitlistEntry {
+    #[serde(rename = "markerID")]
+    marker_id: u32,
+    title: String,
+    position: i64,
+}
+
+/// Someone moved from the waitlist to the people going
+pub(crate) struct Promotion {
+    email: String,
+}
+
+#[derive(Serialize)]
+pub struct Attendee {
+    #[serde(rename = "userID")]
+    user_id: i32,
+    username: String,
+    name: String,
+    surname: String,
+    status: AttendanceStatus,
+    #[serde(with = "ts_seconds")]
+    #[serde(rename = "addTime")]
+    add_time: DateTime<Utc>,
+}
+
+async fn set_status(
+    tx: &mut Transaction<'_, MySql>,
+    user_id: u32,
+    marker_id: u32,
+    status: AttendanceStatus,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO attendance (marker_id, user_id, status) VALUES (?, ?, ?)
+        ON DUPLICATE KEY UPDATE status = VALUES(status)
+        "#,
+        marker_id,
+        user_id,
+        status
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM waitlist WHERE marker_id = ? AND user_id = ?",
+        marker_id,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn current_status(
+    tx: &mut Transaction<'_, MySql>,
+    user_id: u32,
+    marker_id: u32,
+) -> anyhow::Result<Option<AttendanceStatus>> {
+    let status = sqlx::query!(
+        r#"
+        SELECT status as `status: AttendanceStatus`
+        FROM attendance
+        WHERE marker_id = ? AND user_id = ?
+        "#,
+        marker_id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .map(|attendance| attendance.status);
+
+    Ok(status)
+}
+
+async fn count_going(tx: &mut Transaction<'_, MySql>, marker_id: u32) -> anyhow::Result<i64> {
+    let going = sqlx::query!(
+        r#"SELECT COUNT(*) as going FROM attendance WHERE marker_id = ? AND status = "going""#,
+        marker_id
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .going;
+
+    Ok(going)
+}
+
+/// Fills the free places with the people waiting longest. Has to run in the transaction
+/// that freed them, with the marker row locked
+async fn promote(
+    tx: &mut Transaction<'_, MySql>,
+    marker_id: u32,
+    capacity: Option<u32>,
+) -> anyhow::Result<Vec<Promotion>> {
+    let free = match capacity {
+        Some(capacity) => i64::from(capacity) - count_going(tx, marker_id).await?,
+        None => i64::MAX,
+    };
+    if free <= 0 {
+        return Ok(Vec::new());
+    }
+
+    let waiting = sqlx::query!(
+        r#"
+        SELECT w.user_id, u.email
+        FROM waitlist as w
+        INNER JOIN users as u ON u.id = w.user_id
+        WHERE w.marker_id = ?
+        ORDER BY w.id
+        LIMIT ?
+        "#,
+        marker_id,
+        free
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut promoted = Vec::with_capacity(waiting.len());
+    for entry in waiting {
+        set_status(tx, entry.user_id as u32, marker_id, AttendanceStatus::Going).await?;
+        promoted.push(Promotion { email: entry.email });
+    }
+
+    Ok(promoted)
+}
+
+/// Places added by raising the capacity of the marker go to the people waiting longest.
+/// Has to run in the transaction that changed the capacity, with the marker row locked
+pub(crate) async fn fill_capacity(
+    tx: &mut Transaction<'_, MySql>,
+    marker_id: u32,
+) -> anyhow::Result<Vec<Promotion>> {
+    let capacity = sqlx::query!("SELECT capacity FROM markers WHERE id = ?", marker_id)
+        .fetch_one(&mut *tx)
+        .await?
+        .capacity;
+
+    promote(tx, marker_id, capacity).await
+}
+
+/// Emails are sent once the promotion is committed, failing to send one is only logged
+pub(crate) async fn notify_promoted(mailer: &Mailer, title: &str, promoted: Vec<Promotion>) {
+    for promotion in promoted {
+        let body = format!(
+            "Zwolniło się miejsce na wydarzeniu „{}”. Twoje zgłoszenie zostało przeniesione \
+            z listy oczekujących na listę uczestników.",
+            title
+        );
+        let subject = format!("Zwolniło się miejsce: {}", title);
+        if let Err(e) = mailer.send(&promotion.email, &subject, body).await {
+            error!("Failed to notify {}: {}", promotion.email, e);
+        }
+    }
+}
+
+/// Position of the user on the waitlist of the marker, counted from 1
+pub async fn waitlist_position<'e>(
+    executor: impl sqlx::MySqlExecutor<'e>,
+    user_id: u32,
+    marker_id: u32,
+) -> anyhow::Result<Option<i64>> {
+    let position = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as position
+        FROM waitlist
+        WHERE marker_id = ?
+        AND id <= (SELECT id FROM waitlist WHERE marker_id = ? AND user_id = ?)
+        "#,
+        marker_id,
+        marker_id,
+        user_id
+    )
+    .fetch_one(executor)
+    .await?
+    .position;
+
+    Ok((position > 0).then_some(position))
+}
+
+/// Every waitlist the user is on, in the order they joined them
+pub async fn show_user_waitlist(
+    db: &sqlx::MySqlPool,
+    user_id: u32,
+) -> anyhow::Result<Vec<WaitlistEntry>> {
+    let entries = sqlx::query_as!(
+        WaitlistEntry,
+        r#"
+        SELECT w.marker_id, m.title,
+        (SELECT COUNT(*) FROM waitlist as o WHERE o.marker_id = w.marker_id AND o.id <= w.id)
+            as `position!`
+        FROM waitlist as w
+        INNER JOIN markers as m ON m.id = w.marker_id
+        WHERE w.user_id = ? AND m.deleted_at IS NULL
+        ORDER BY w.id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(entries)
+}
+
+impl AttendanceRequest {
+    /// Sets the attendance of the user, replacing the previous one. The marker row stays locked
+    /// until the end, so two people can't both take the last place
+    pub async fn attend(
+        &self,
+        db: &sqlx::MySqlPool,
+        mailer: &Mailer,
+        user_id: u32,
+        marker_id: u32,
+    ) -> anyhow::Result<Signup> {
+        let mut tx = db.begin().await?;
+
+        let marker = sqlx::query!(
+            r#"
+            SELECT title, capacity,
+            (SELECT attendance FROM categories WHERE categories.id = markers.category_id)
+                as `attendance!: bool`
+            FROM markers
+            WHERE id = ? AND archived_at IS NULL AND hidden_at IS NULL AND deleted_at IS NULL
+            AND NOT draft
+            FOR UPDATE
+            "#,
+            marker_id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(AttendanceError::NotFound)?;
+
+        // Only categories of events people gather at take attendance
+        if !marker.attendance {
+            return Err(AttendanceError::NotAllowed.into());
+        }
+
+        let current = current_status(&mut tx, user_id, marker_id).await?;
+
+        let mut promoted = Vec::new();
+        let signup = match self.status {
+            AttendanceStatus::Going if current == Some(AttendanceStatus::Going) => Signup::Going,
+            AttendanceStatus::Going => {
+                let full = match marker.capacity {
+                    Some(capacity) => count_going(&mut tx, marker_id).await? >= i64::from(capacity),
+                    None => false,
+                };
+                if full {
+                    // Signing up again keeps the place in the queue
+                    sqlx::query!(
+                        "INSERT IGNORE INTO waitlist (marker_id, user_id) VALUES (?, ?)",
+                        marker_id,
+                        user_id
+                    )
+                    .execute(&mut tx)
+                    .await?;
+                    let position = waitlist_position(&mut tx, user_id, marker_id)
+                        .await?
+                        .unwrap_or_default();
+                    Signup::Waiting { position }
+                } else {
+                    set_status(&mut tx, user_id, marker_id, AttendanceStatus::Going).await?;
+                    Signup::Going
+                }
+            }
+            AttendanceStatus::Interested => {
+                set_status(&mut tx, user_id, marker_id, AttendanceStatus::Interested).await?;
+                if current == Some(AttendanceStatus::Going) {
+                    promoted = promote(&mut tx, marker_id, marker.capacity).await?;
+                }
+                Signup::Interested
+            }
+        };
+
+        tx.commit().await?;
+        notify_promoted(mailer, &marker.title, promoted).await;
+
+        Ok(signup)
+    }
+}
+
+/// Removes the user from the attendees and the waitlist of the marker, the freed place goes
+/// to whoever waits longest. Returns whether the user was signed up at all
+pub async fn leave_marker(
+    db: &sqlx::MySqlPool,
+    mailer: &Mailer,
+    user_id: u32,
+    marker_id: u32,
+) -> anyhow::Result<bool> {
+    let mut tx = db.begin().await?;
+
+    let marker = sqlx::query!(
+        "SELECT title, capacity FROM markers WHERE id = ? FOR UPDATE",
+        marker_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(AttendanceError::NotFound)?;
+
+    let was_going =
+        current_status(&mut tx, user_id, marker_id).await? == Some(AttendanceStatus::Going);
+
+    let left = sqlx::query!(
+        "DELETE FROM attendance WHERE marker_id = ? AND user_id = ?",
+        marker_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    let left_waitlist = sqlx::query!(
+        "DELETE FROM waitlist WHERE marker_id = ? AND user_id = ?",
+        marker_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    let promoted = if was_going {
+        promote(&mut tx, marker_id, marker.capacity).await?
+    } else {
+        Vec::new()
+    };
+
+    tx.commit().await?;
+    notify_promoted(mailer, &marker.title, promoted).await;
+
+    Ok(left + left_waitlist > 0)
+}
+
+/// Everyone attending the marker, in the order they signed up. Only the author may see them,
+/// and only until the marker is trashed
+pub async fn show_attendees(
+    db: &sqlx::MySqlPool,
+    owner: u32,
+    marker_id: u32,
+) -> anyhow::Result<Vec<Attendee>> {
+    sqlx::query!(
+        "SELECT id FROM markers WHERE id = ? AND user_id = ? AND deleted_at IS NULL",
+        marker_id,
+        owner
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(AttendanceError::NotFound)?;
+
+    let attendees = sqlx::query_as!(
+        Attendee,
+        r#"
+        SELECT a.user_id, u.name as username, ext.name, ext.surname,
+        a.status as `status: AttendanceStatus`, a.add_time
+        FROM attendance as a
+        INNER JOIN users as u ON u.id = a.user_id
+        INNER JOIN full_users_info as ext ON ext.id = a.user_id
+        WHERE a.marker_id = ?
+        ORDER BY a.add_time, a.user_id
+        "#,
+        marker_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(attendees)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use sqlx::MySqlPool;
+
+    async fn attend(
+        db: &MySqlPool,
+        status: AttendanceStatus,
+        user_id: u32,
+        marker_id: u32,
+    ) -> anyhow::Result<Signup> {
+        let mailer = Mailer::from_env()?;
+        AttendanceRequest { status }
+            .attend(db, &mailer, user_id, marker_id)
+            .await
+    }
+
+    #[sqlx::test]
+    async fn full_events_queue_on_the_waitlist(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let mailer = Mailer::from_env()?;
+        let jan = testing::add_user(&db, "jan").await?;
+        let anna = testing::add_user(&db, "anna").await?;
+        let piotr = testing::add_user(&db, "piotr").await?;
+        let marker = testing::add_marker(&db, jan, "Koncert").await?;
+        sqlx::query(r#"UPDATE markers SET category_id = 2, capacity = 1 WHERE id = ?"#)
+            .bind(marker)
+            .execute(&db)
+            .await?;
+
+        let signup = attend(&db, AttendanceStatus::Going, anna, marker).await?;
+        assert!(matches!(signup, Signup::Going));
+        // Signing up again doesn't count against the capacity
+        let signup = attend(&db, AttendanceStatus::Going, anna, marker).await?;
+        assert!(matches!(signup, Signup::Going));
+        let signup = attend(&db, AttendanceStatus::Going, piotr, marker).await?;
+        assert!(matches!(signup, Signup::Waiting { position: 1 }));
+        assert_eq!(waitlist_position(&db, piotr, marker).await?, Some(1));
+
+        assert!(leave_marker(&db, &mailer, anna, marker).await?);
+        assert!(!leave_marker(&db, &mailer, anna, marker).await?);
+        assert_eq!(waitlist_position(&db, piotr, marker).await?, None);
+        let attendees = show_attendees(&db, jan, marker).await?;
+        assert_eq!(attendees.len(), 1);
+        assert_eq!(attendees[0].user_id, piotr as i32);
+        assert!(attendees[0].status == AttendanceStatus::Going);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn help_requests_take_no_attendance(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let jan = testing::add_user(&db, "jan").await?;
+        let anna = testing::add_user(&db, "anna").await?;
+        let marker = testing::add_marker(&db, jan, "Zakupy").await?;
+
+        let going = attend(&db, AttendanceStatus::Going, anna, marker).await;
+        let error = going
+            .err()
+            .and_then(|e| e.downcast::<AttendanceError>().ok());
+        assert!(matches!(error, Some(AttendanceError::NotAllowed)));
+        let missing = attend(&db, AttendanceStatus::Going, anna, marker + 1).await;
+        let error = missing
+            .err()
+            .and_then(|e| e.downcast::<AttendanceError>().ok());
+        assert!(matches!(error, Some(AttendanceError::NotFound)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use sqlx::{MySql, QueryBuilder};
+
+/// Widest viewport, in degrees, the API agrees to serve. Poland fits in it with some margin
+pub const MAX_SPAN: f64 = 12.0;
+
+/// Map viewport. Boxes crossing the antimeridian are not supported, so `west` has to be
+/// smaller than `east`
+pub struct BoundingBox {
+    south: f64,
+    west: f64,
+    north: f64,
+    east: f64,
+}
+
+impl BoundingBox {
+    pub fn new(south: f64, west: f64, north: f64, east: f64) -> Result<Self, &'static str> {
+        Self::with_max_span(south, west, north, east, (MAX_SPAN, MAX_SPAN))
+    }
+
+    /// Same as `new`, with the widest latitude and longitude span given by the caller
+    pub fn with_max_span(
+        south: f64,
+        west: f64,
+        north: f64,
+        east: f64,
+        (max_lat_span, max_long_span): (f64, f64),
+    ) -> Result<Self, &'static str> {
+        if !(-90.0..=90.0).contains(&south) || !(-90.0..=90.0).contains(&north) {
+            return Err("Szerokość geograficzna musi mieścić się w przedziale od -90 do 90");
+        }
+        if !(-180.0..=180.0).contains(&west) || !(-180.0..=180.0).contains(&east) {
+            return Err("Długość geograficzna musi mieścić się w przedziale od -180 do 180");
+        }
+        if south > north || west > east {
+            return Err("Nieprawidłowy obszar mapy");
+        }
+        if north - south > max_lat_span || east - west > max_long_span {
+            return Err("Obszar mapy jest zbyt duży");
+        }
+
+        Ok(Self {
+            south,
+            west,
+            north,
+            east,
+        })
+    }
+
+    pub fn south(&self) -> f64 {
+        self.south
+    }
+
+    pub fn west(&self) -> f64 {
+        self.west
+    }
+
+    pub fn north(&self) -> f64 {
+        self.north
+    }
+
+    pub fn east(&self) -> f64 {
+        self.east
+    }
+
+    /// Appends the condition to an already started `WHERE` clause
+    pub(crate) fn push_condition(&self, builder: &mut QueryBuilder<'_, MySql>) {
+        builder
+            .push(" AND latitude BETWEEN ")
+            .push_bind(self.south)
+            .push(" AND ")
+            .push_bind(self.north)
+            .push(" AND longitude BETWEEN ")
+            .push_bind(self.west)
+            .push(" AND ")
+            .push_bind(self.east);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_the_span() {
+        assert!(BoundingBox::new(49.0, 14.0, 55.0, 24.0).is_ok());
+        assert!(BoundingBox::new(-90.0, -180.0, 90.0, 180.0).is_err());
+        assert!(BoundingBox::with_max_span(-90.0, -180.0, 90.0, 180.0, (180.0, 360.0)).is_ok());
+        assert!(BoundingBox::with_max_span(0.0, 0.0, 10.0, 200.0, (180.0, 360.0)).is_err());
+    }
+}
@@ -0,0 +1,382 @@
+use super::{nullable, MarkerError};
+use rocket::FromFormField;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use sqlx::mysql::MySqlDatabaseError;
+use std::fmt;
+use validator::{Validate, ValidationError};
+
+#[derive(Debug)]
+pub enum CategoryError {
+    NotFound,
+    SlugTaken,
+    InvalidParent,
+    InUse,
+}
+
+impl fmt::Display for CategoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "category not found"),
+            Self::SlugTaken => write!(f, "slug already used by another category"),
+            Self::InvalidParent => write!(f, "parent missing or not a top-level category"),
+            Self::InUse => write!(f, "category still has markers or subcategories"),
+        }
+    }
+}
+
+impl std::error::Error for CategoryError {}
+
+#[derive(Serialize)]
+pub struct Category {
+    id: u32,
+    slug: String,
+    #[serde(rename = "namePl")]
+    name_pl: String,
+    #[serde(rename = "nameEn")]
+    name_en: String,
+    icon: Option<String>,
+    colour: Option<String>,
+    #[serde(rename = "parentID")]
+    parent_id: Option<u32>,
+    /// Markers of the category take attendance of the people going
+    attendance: bool,
+    /// Markers of the category are neighbour help requests others can claim
+    help: bool,
+}
+
+/// Marker types from before categories, still accepted and sent for older clients.
+/// Each one stands for the category it was migrated to
+#[derive(Serialize, Deserialize, FromFormField, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EventType {
+    NeighborHelp,
+    Happening,
+    Charity,
+    MassEvent,
+}
+
+impl EventType {
+    pub fn slug(self) -> &'static str {
+        match self {
+            Self::NeighborHelp => "neighbor-help",
+            Self::Happening => "happening",
+            Self::Charity => "charity",
+            Self::MassEvent => "mass-event",
+        }
+    }
+
+    fn from_slug(slug: &str) -> Option<Self> {
+        match slug {
+            "neighbor-help" => Some(Self::NeighborHelp),
+            "happening" => Some(Self::Happening),
+            "charity" => Some(Self::Charity),
+            "mass-event" => Some(Self::MassEvent),
+            _ => None,
+        }
+    }
+}
+
+/// Category of a listed marker, sent as its slug together with the matching `type`
+#[derive(Deserialize, sqlx::FromRow)]
+pub struct MarkerCategory {
+    #[serde(rename = "category")]
+    #[sqlx(rename = "category")]
+    pub slug: String,
+}
+
+impl Serialize for MarkerCategory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("category", &self.slug)?;
+        if let Some(r#type) = EventType::from_slug(&self.slug) {
+            map.serialize_entry("type", &r#type)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct NewCategory<'r> {
+    #[validate(length(min = 1, max = 50), custom = "validate_slug")]
+    slug: &'r str,
+    #[serde(rename = "namePl")]
+    #[validate(length(min = 1, max = 100))]
+    name_pl: &'r str,
+    #[serde(rename = "nameEn")]
+    #[validate(length(min = 1, max = 100))]
+    name_en: &'r str,
+    #[serde(default)]
+    #[validate(length(min = 1, max = 50))]
+    icon: Option<&'r str>,
+    #[serde(default)]
+    #[validate(custom = "validate_colour")]
+    colour: Option<&'r str>,
+    #[serde(rename = "parentID")]
+    #[serde(default)]
+    parent_id: Option<u32>,
+    #[serde(default)]
+    attendance: bool,
+    #[serde(default)]
+    help: bool,
+}
+
+/// Partial update of a category, every field left out of the request stays untouched.
+/// The icon, colour and parent are cleared by sending `null`
+#[derive(Deserialize, Validate)]
+pub struct CategoryUpdate<'r> {
+    #[validate(length(min = 1, max = 50), custom = "validate_slug")]
+    slug: Option<&'r str>,
+    #[serde(rename = "namePl")]
+    #[validate(length(min = 1, max = 100))]
+    name_pl: Option<&'r str>,
+    #[serde(rename = "nameEn")]
+    #[validate(length(min = 1, max = 100))]
+    name_en: Option<&'r str>,
+    #[serde(borrow, default, deserialize_with = "nullable::deserialize")]
+    #[validate(length(min = 1, max = 50))]
+    icon: Option<Option<&'r str>>,
+    #[serde(borrow, default, deserialize_with = "nullable::deserialize")]
+    #[validate(custom = "validate_colour")]
+    colour: Option<Option<&'r str>>,
+    #[serde(rename = "parentID")]
+    #[serde(default, deserialize_with = "nullable::deserialize")]
+    parent_id: Option<Option<u32>>,
+    attendance: Option<bool>,
+    help: Option<bool>,
+}
+
+/// Slugs end up in URLs, so only lowercase letters, digits and dashes are allowed
+fn validate_slug(slug: &str) -> Result<(), ValidationError> {
+    if slug
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        Ok(())
+    } else {
+        Err(ValidationError::new("slug"))
+    }
+}
+
+/// Colours are given as `#rrggbb`
+fn validate_colour(colour: &str) -> Result<(), ValidationError> {
+    match colour.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(()),
+        _ => Err(ValidationError::new("colour")),
+    }
+}
+
+/// Every category, parents before their children
+pub async fn show_categories(db: &sqlx::MySqlPool) -> anyhow::Result<Vec<Category>> {
+    let categories = sqlx::query_as!(
+        Category,
+        r#"
+        SELECT id, slug, name_pl, name_en, icon, colour, parent_id,
+        attendance as `attendance: bool`, help as `help: bool`
+        FROM categories
+        ORDER BY COALESCE(parent_id, id), parent_id IS NOT NULL, name_pl
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(categories)
+}
+
+pub async fn category_exists<'e>(
+    executor: impl sqlx::MySqlExecutor<'e>,
+    id: u32,
+) -> anyhow::Result<bool> {
+    let category = sqlx::query!("SELECT id FROM categories WHERE id = ?", id)
+        .fetch_optional(executor)
+        .await?;
+
+    Ok(category.is_some())
+}
+
+/// Category given by its id or, by older clients, as a marker type. Returns `None` when
+/// neither is given
+pub async fn resolve_category<'e>(
+    executor: impl sqlx::MySqlExecutor<'e>,
+    id: Option<u32>,
+    r#type: Option<EventType>,
+) -> anyhow::Result<Option<u32>> {
+    let category = match (id, r#type) {
+        (Some(id), _) => sqlx::query!("SELECT id FROM categories WHERE id = ?", id)
+            .fetch_optional(executor)
+            .await?
+            .map(|category| category.id),
+        (None, Some(r#type)) => {
+            sqlx::query!("SELECT id FROM categories WHERE slug = ?", r#type.slug())
+                .fetch_optional(executor)
+                .await?
+                .map(|category| category.id)
+        }
+        (None, None) => return Ok(None),
+    };
+
+    category
+        .map(Some)
+        .ok_or_else(|| MarkerError::UnknownCategory.into())
+}
+
+async fn check_slug(db: &sqlx::MySqlPool, slug: &str, id: Option<u32>) -> anyhow::Result<()> {
+    let taken = sqlx::query!(
+        "SELECT id FROM categories WHERE slug = ? AND id <> COALESCE(?, 0)",
+        slug,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    match taken {
+        Some(_) => Err(CategoryError::SlugTaken.into()),
+        None => Ok(()),
+    }
+}
+
+/// MySQL error number of a duplicate key
+const ER_DUP_ENTRY: u16 = 1062;
+
+/// `check_slug` can't see a category added in the meantime, so the unique key on slugs
+/// has the last word
+fn slug_taken(e: sqlx::Error) -> anyhow::Error {
+    let duplicate = match &e {
+        sqlx::Error::Database(db_error) => db_error
+            .try_downcast_ref::<MySqlDatabaseError>()
+            .map_or(false, |db_error| db_error.number() == ER_DUP_ENTRY),
+        _ => false,
+    };
+
+    if duplicate {
+        CategoryError::SlugTaken.into()
+    } else {
+        e.into()
+    }
+}
+
+/// Categories are nested one level deep at most, so the parent has to be a top-level one
+async fn check_parent(db: &sqlx::MySqlPool, parent_id: u32, id: Option<u32>) -> anyhow::Result<()> {
+    let parent = sqlx::query!(
+        "SELECT id FROM categories WHERE id = ? AND parent_id IS NULL AND id <> COALESCE(?, 0)",
+        parent_id,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+    if parent.is_none() {
+        return Err(CategoryError::InvalidParent.into());
+    }
+
+    if let Some(id) = id {
+        let children = sqlx::query!(
+            "SELECT COUNT(*) as children FROM categories WHERE parent_id = ?",
+            id
+        )
+        .fetch_one(db)
+        .await?
+        .children;
+        if children > 0 {
+            return Err(CategoryError::InvalidParent.into());
+        }
+    }
+
+    Ok(())
+}
+
+impl NewCategory<'_> {
+    pub async fn add_category(&self, db: &sqlx::MySqlPool) -> anyhow::Result<u64> {
+        check_slug(db, self.slug, None).await?;
+        if let Some(parent_id) = self.parent_id {
+            check_parent(db, parent_id, None).await?;
+        }
+
+        let added = sqlx::query!(
+            r#"
+            INSERT INTO categories (slug, name_pl, name_en, icon, colour, parent_id, attendance, help)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            self.slug,
+            self.name_pl,
+            self.name_en,
+            self.icon,
+            self.colour,
+            self.parent_id,
+            self.attendance,
+            self.help
+        )
+        .execute(db)
+        .await
+        .map_err(slug_taken)?;
+
+        Ok(added.last_insert_id())
+    }
+}
+
+impl CategoryUpdate<'_> {
+    pub async fn update_category(&self, db: &sqlx::MySqlPool, id: u32) -> anyhow::Result<()> {
+        if !category_exists(db, id).await? {
+            return Err(CategoryError::NotFound.into());
+        }
+        if let Some(slug) = self.slug {
+            check_slug(db, slug, Some(id)).await?;
+        }
+        if let Some(Some(parent_id)) = self.parent_id {
+            check_parent(db, parent_id, Some(id)).await?;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE categories SET
+            slug = COALESCE(?, slug), name_pl = COALESCE(?, name_pl), name_en = COALESCE(?, name_en),
+            icon = IF(?, ?, icon), colour = IF(?, ?, colour), parent_id = IF(?, ?, parent_id),
+            attendance = COALESCE(?, attendance), help = COALESCE(?, help)
+            WHERE id = ?
+            "#,
+            self.slug,
+            self.name_pl,
+            self.name_en,
+            self.icon.is_some(),
+            self.icon.flatten(),
+            self.colour.is_some(),
+            self.colour.flatten(),
+            self.parent_id.is_some(),
+            self.parent_id.flatten(),
+            self.attendance,
+            self.help,
+            id
+        )
+        .execute(db)
+        .await
+        .map_err(slug_taken)?;
+
+        Ok(())
+    }
+}
+
+/// Only categories nothing refers to can be deleted, markers have to be moved elsewhere first
+pub async fn delete_category(db: &sqlx::MySqlPool, id: u32) -> anyhow::Result<()> {
+    if !category_exists(db, id).await? {
+        return Err(CategoryError::NotFound.into());
+    }
+
+    let in_use = sqlx::query!(
+        r#"
+        SELECT EXISTS(SELECT id FROM markers WHERE category_id = ?)
+        OR EXISTS(SELECT id FROM categories WHERE parent_id = ?) as `in_use: bool`
+        "#,
+        id,
+        id
+    )
+    .fetch_one(db)
+    .await?
+    .in_use;
+    if in_use {
+        return Err(CategoryError::InUse.into());
+    }
+
+    sqlx::query!("DELETE FROM categories WHERE id = ?", id)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
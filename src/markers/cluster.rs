@@ -0,0 +1,182 @@
+use serde::Serialize;
+use sqlx::QueryBuilder;
+use std::collections::HashMap;
+
+use super::{BoundingBox, MarkerFilter};
+
+/// Radius of a cluster on screen, tiles are assumed to be 256 px wide
+const CLUSTER_SIZE_PX: f64 = 60.0;
+const TILE_SIZE_PX: f64 = 256.0;
+/// From this zoom on markers are never clustered
+pub const MAX_CLUSTER_ZOOM: u8 = 17;
+/// Cells with this many markers or less are sent as separate pins
+const SPARSE_CELL_SIZE: i64 = 3;
+/// Widest viewport in degrees of latitude and longitude. Clusters stay small however many
+/// markers they hold, so the whole map may be shown at low zooms
+pub const MAX_CLUSTER_SPAN: (f64, f64) = (180.0, 360.0);
+/// Upper bound of pins in one response, the newest markers are sent first
+const MAX_PINS: u32 = 1000;
+
+#[derive(Serialize)]
+pub struct Cluster {
+    latitude: f64,
+    longitude: f64,
+    count: i64,
+    /// Number of markers by category id
+    categories: HashMap<u32, i64>,
+}
+
+/// Just enough of a marker to draw it, the rest can be fetched by id
+#[derive(Serialize, sqlx::FromRow)]
+pub struct MarkerPin {
+    id: u32,
+    latitude: f64,
+    longitude: f64,
+    title: String,
+    #[serde(rename = "categoryID")]
+    category_id: u32,
+}
+
+#[derive(Serialize, Default)]
+pub struct Clusters {
+    clusters: Vec<Cluster>,
+    markers: Vec<MarkerPin>,
+    /// Set when there were more than `MAX_PINS` pins to send, the client should zoom in
+    truncated: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct CellRow {
+    cell_lat: i64,
+    cell_long: i64,
+    category_id: u32,
+    count: i64,
+    lat_sum: f64,
+    long_sum: f64,
+}
+
+/// Side of a grid cell in degrees
+fn cell_size(zoom: u8) -> f64 {
+    360.0 / 2f64.powi(zoom.into()) * CLUSTER_SIZE_PX / TILE_SIZE_PX
+}
+
+/// Groups markers in the viewport into grid cells matching the zoom level.
+/// Crowded cells are returned as clusters, sparse ones as individual pins
+pub async fn cluster_markers(
+    db: &sqlx::MySqlPool,
+    bbox: &BoundingBox,
+    zoom: u8,
+    filter: MarkerFilter<'_>,
+) -> anyhow::Result<Clusters> {
+    if zoom >= MAX_CLUSTER_ZOOM {
+        let (markers, truncated) = show_pins(db, bbox, &filter, None).await?;
+        return Ok(Clusters {
+            clusters: Vec::new(),
+            markers,
+            truncated,
+        });
+    }
+
+    let size = cell_size(zoom);
+    let mut query = QueryBuilder::new("SELECT CAST(FLOOR(latitude / ");
+    query
+        .push_bind(size)
+        .push(") AS SIGNED) AS cell_lat, CAST(FLOOR(longitude / ")
+        .push_bind(size)
+        .push(
+            r#") AS SIGNED) AS cell_long, category_id, COUNT(*) AS count,
+            SUM(latitude) AS lat_sum, SUM(longitude) AS long_sum
+            FROM markers
+            WHERE TRUE"#,
+        );
+    bbox.push_condition(&mut query);
+    filter.push_conditions(&mut query);
+    query.push(" GROUP BY cell_lat, cell_long, category_id");
+
+    let rows: Vec<CellRow> = query.build_query_as().fetch_all(db).await?;
+
+    let mut cells: HashMap<(i64, i64), Cluster> = HashMap::new();
+    for row in rows {
+        let cluster = cells
+            .entry((row.cell_lat, row.cell_long))
+            .or_insert_with(|| Cluster {
+                latitude: 0.0,
+                longitude: 0.0,
+                count: 0,
+                categories: HashMap::new(),
+            });
+        // Sums for now, turned into the centroid below
+        cluster.latitude += row.lat_sum;
+        cluster.longitude += row.long_sum;
+        cluster.count += row.count;
+        *cluster.categories.entry(row.category_id).or_default() += row.count;
+    }
+
+    let mut result = Clusters::default();
+    let mut has_sparse_cells = false;
+    for mut cluster in cells.into_values() {
+        if cluster.count <= SPARSE_CELL_SIZE {
+            has_sparse_cells = true;
+        } else {
+            cluster.latitude /= cluster.count as f64;
+            cluster.longitude /= cluster.count as f64;
+            result.clusters.push(cluster);
+        }
+    }
+
+    if has_sparse_cells {
+        (result.markers, result.truncated) = show_pins(db, bbox, &filter, Some(size)).await?;
+    }
+
+    Ok(result)
+}
+
+/// Pins in the viewport, at most `MAX_PINS` of them, and whether some were left out. Given
+/// the size of grid cells, only pins lying in sparse cells are returned
+async fn show_pins(
+    db: &sqlx::MySqlPool,
+    bbox: &BoundingBox,
+    filter: &MarkerFilter<'_>,
+    cell_size: Option<f64>,
+) -> anyhow::Result<(Vec<MarkerPin>, bool)> {
+    let mut query = QueryBuilder::new(
+        r#"
+        SELECT id, latitude, longitude, title, category_id
+        FROM markers"#,
+    );
+
+    // Sparse cells are found again by the database, binding them one by one could run past
+    // the limit of placeholders in a query
+    if let Some(size) = cell_size {
+        query
+            .push(" INNER JOIN (SELECT CAST(FLOOR(latitude / ")
+            .push_bind(size)
+            .push(") AS SIGNED) AS sparse_lat, CAST(FLOOR(longitude / ")
+            .push_bind(size)
+            .push(") AS SIGNED) AS sparse_long FROM markers WHERE TRUE");
+        bbox.push_condition(&mut query);
+        filter.push_conditions(&mut query);
+        query
+            .push(" GROUP BY sparse_lat, sparse_long HAVING COUNT(*) <= ")
+            .push_bind(SPARSE_CELL_SIZE)
+            .push(") AS sparse ON sparse_lat = CAST(FLOOR(latitude / ")
+            .push_bind(size)
+            .push(") AS SIGNED) AND sparse_long = CAST(FLOOR(longitude / ")
+            .push_bind(size)
+            .push(") AS SIGNED)");
+    }
+
+    query.push(" WHERE TRUE");
+    bbox.push_condition(&mut query);
+    filter.push_conditions(&mut query);
+    // One pin more than sent tells whether any were left out
+    query
+        .push(" ORDER BY add_time DESC, id DESC LIMIT ")
+        .push_bind(MAX_PINS + 1);
+
+    let mut pins: Vec<MarkerPin> = query.build_query_as().fetch_all(db).await?;
+    let truncated = pins.len() > MAX_PINS as usize;
+    pins.truncate(MAX_PINS as usize);
+
+    Ok((pins, truncated))
+}
@@ -0,0 +1,217 @@
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::QueryBuilder;
+use std::fmt;
+use validator::Validate;
+
+use super::pagination::{Cursor, Page, PageRequest, Paginated};
+
+#[derive(Debug)]
+pub enum CommentError {
+    MarkerNotFound,
+    InvalidParent,
+    NotFound,
+}
+
+impl fmt::Display for CommentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MarkerNotFound => write!(f, "marker not found"),
+            Self::InvalidParent => write!(f, "parent comment not found or is a reply itself"),
+            Self::NotFound => write!(f, "comment not found"),
+        }
+    }
+}
+
+impl std::error::Error for CommentError {}
+
+#[derive(Deserialize, Validate)]
+pub struct NewComment<'r> {
+    #[validate(length(min = 1, max = 2000))]
+    body: &'r str,
+    /// Comment being replied to, replies can't be replied to
+    #[serde(rename = "parentID")]
+    #[serde(default)]
+    parent_id: Option<u32>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct Comment {
+    id: u32,
+    #[serde(rename = "userID")]
+    user_id: i32,
+    username: String,
+    body: String,
+    #[serde(with = "ts_seconds")]
+    #[serde(rename = "addTime")]
+    add_time: DateTime<Utc>,
+}
+
+/// Top-level comment together with all of its replies, oldest first
+#[derive(Serialize)]
+pub struct Thread {
+    #[serde(flatten)]
+    comment: Comment,
+    replies: Vec<Comment>,
+}
+
+#[derive(sqlx::FromRow)]
+struct Reply {
+    parent_id: u32,
+    #[sqlx(flatten)]
+    comment: Comment,
+}
+
+impl Paginated for Thread {
+    fn cursor(&self) -> Cursor {
+        Cursor::new(self.comment.add_time, self.comment.id)
+    }
+}
+
+/// Comments joined with their authors. Wrapped in a derived table, so that the columns used
+/// by pagination aren't ambiguous
+const COMMENT_COLUMNS: &str = r#"
+        SELECT * FROM (
+            SELECT c.id, c.marker_id, c.parent_id, c.user_id, u.name as username, c.body, c.add_time
+            FROM comments as c
+            INNER JOIN users as u ON u.id = c.user_id
+        ) AS c
+        WHERE TRUE"#;
+
+/// Hidden markers and drafts can only be discussed by their authors
+async fn find_marker(
+    db: &sqlx::MySqlPool,
+    viewer_id: Option<u32>,
+    marker_id: u32,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        SELECT id FROM markers
+        WHERE id = ? AND deleted_at IS NULL AND ((hidden_at IS NULL AND NOT draft) OR user_id = ?)
+        "#,
+        marker_id,
+        viewer_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(CommentError::MarkerNotFound)?;
+
+    Ok(())
+}
+
+impl NewComment<'_> {
+    pub async fn add_comment(
+        &self,
+        db: &sqlx::MySqlPool,
+        user_id: u32,
+        marker_id: u32,
+    ) -> anyhow::Result<u64> {
+        find_marker(db, Some(user_id), marker_id).await?;
+
+        if let Some(parent_id) = self.parent_id {
+            sqlx::query!(
+                "SELECT id FROM comments WHERE id = ? AND marker_id = ? AND parent_id IS NULL",
+                parent_id,
+                marker_id
+            )
+            .fetch_optional(db)
+            .await?
+            .ok_or(CommentError::InvalidParent)?;
+        }
+
+        let added = sqlx::query!(
+            "INSERT INTO comments (marker_id, parent_id, user_id, body, add_time) VALUES (?, ?, ?, ?, ?)",
+            marker_id,
+            self.parent_id,
+            user_id,
+            self.body,
+            Utc::now()
+        )
+        .execute(db)
+        .await?;
+
+        Ok(added.last_insert_id())
+    }
+}
+
+/// Threads of the marker, newest first, with every reply attached
+pub async fn show_comments(
+    db: &sqlx::MySqlPool,
+    viewer_id: Option<u32>,
+    marker_id: u32,
+    page: PageRequest,
+) -> anyhow::Result<Page<Thread>> {
+    find_marker(db, viewer_id, marker_id).await?;
+
+    let mut query = QueryBuilder::new(COMMENT_COLUMNS);
+    query
+        .push(" AND marker_id = ")
+        .push_bind(marker_id)
+        .push(" AND parent_id IS NULL");
+    page.push_condition(&mut query);
+    page.push_order_and_limit(&mut query);
+
+    let comments: Vec<Comment> = query.build_query_as().fetch_all(db).await?;
+    let mut page = page.into_page(
+        comments
+            .into_iter()
+            .map(|comment| Thread {
+                comment,
+                replies: Vec::new(),
+            })
+            .collect(),
+    );
+
+    let threads = page.items_mut();
+    if threads.is_empty() {
+        return Ok(page);
+    }
+
+    let mut query = QueryBuilder::new(COMMENT_COLUMNS);
+    query.push(" AND parent_id IN (");
+    let mut ids = query.separated(", ");
+    for thread in threads.iter() {
+        ids.push_bind(thread.comment.id);
+    }
+    ids.push_unseparated(") ORDER BY add_time, id");
+
+    let replies: Vec<Reply> = query.build_query_as().fetch_all(db).await?;
+    for reply in replies {
+        if let Some(thread) = threads
+            .iter_mut()
+            .find(|thread| thread.comment.id == reply.parent_id)
+        {
+            thread.replies.push(reply.comment);
+        }
+    }
+
+    Ok(page)
+}
+
+/// Comments can be deleted by their authors and by the author of the marker.
+/// Replies go together with the comment they answer
+pub async fn delete_comment(
+    db: &sqlx::MySqlPool,
+    user_id: u32,
+    comment_id: u32,
+) -> anyhow::Result<()> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE c FROM comments as c
+        INNER JOIN markers as m ON m.id = c.marker_id
+        WHERE c.id = ? AND (c.user_id = ? OR m.user_id = ?)
+        "#,
+        comment_id,
+        user_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if deleted.rows_affected() == 0 {
+        return Err(CommentError::NotFound.into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::fmt;
+
+use super::{ContactInfo, FullMarkerOwned};
+
+/// Markers whose contact info a user may reveal within `REVEAL_WINDOW_MINUTES`,
+/// revealing the same marker again doesn't count
+pub const DEFAULT_REVEAL_LIMIT: i64 = 20;
+const REVEAL_WINDOW_MINUTES: i64 = 60;
+
+#[derive(Debug)]
+pub enum ContactError {
+    NotFound,
+    RateLimited,
+}
+
+impl fmt::Display for ContactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "marker not found"),
+            Self::RateLimited => write!(f, "too many contact info reveals"),
+        }
+    }
+}
+
+impl std::error::Error for ContactError {}
+
+/// Someone who looked at the contact info of a marker
+#[derive(Serialize)]
+pub struct ContactView {
+    #[serde(rename = "userID")]
+    user_id: i32,
+    username: String,
+    #[serde(with = "ts_seconds")]
+    #[serde(rename = "viewTime")]
+    view_time: DateTime<Utc>,
+}
+
+/// Public listings leave contact info out, it has to be revealed marker by marker
+pub(crate) fn hide_contact_info<T: AsMut<FullMarkerOwned>>(markers: &mut [T]) {
+    for marker in markers {
+        marker.as_mut().contact_info = None;
+    }
+}
+
+/// Hands the contact info of the marker to a signed in user and records that they have seen it.
+/// Authors get their own without any limits
+pub async fn reveal_contact_info(
+    db: &sqlx::MySqlPool,
+    user_id: u32,
+    marker_id: u32,
+    limit: i64,
+) -> anyhow::Result<ContactInfo> {
+    let mut tx = db.begin().await?;
+
+    let marker = sqlx::query!(
+        r#"
+        SELECT user_id, contact_info as `contact_info: sqlx::types::Json<ContactInfo>`
+        FROM markers
+        WHERE id = ? AND deleted_at IS NULL AND ((hidden_at IS NULL AND NOT draft) OR user_id = ?)
+        "#,
+        marker_id,
+        user_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(ContactError::NotFound)?;
+    if marker.user_id as u32 == user_id {
+        return Ok(marker.contact_info.0);
+    }
+
+    // Locks the reveals of the user, so parallel requests can't slip past the limit
+    let since = Utc::now() - Duration::minutes(REVEAL_WINDOW_MINUTES);
+    let recent = sqlx::query!(
+        r#"
+        SELECT COUNT(DISTINCT marker_id) as revealed,
+        COALESCE(SUM(marker_id = ?), 0) > 0 as `seen: bool`
+        FROM contact_reveals
+        WHERE user_id = ? AND add_time >= ?
+        FOR UPDATE
+        "#,
+        marker_id,
+        user_id,
+        since
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    if !recent.seen && recent.revealed >= limit {
+        return Err(ContactError::RateLimited.into());
+    }
+
+    sqlx::query!(
+        "INSERT INTO contact_reveals (marker_id, user_id) VALUES (?, ?)",
+        marker_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(marker.contact_info.0)
+}
+
+/// Who revealed the contact info of the marker, latest first. Only the author may see it
+pub async fn show_contact_views(
+    db: &sqlx::MySqlPool,
+    owner: u32,
+    marker_id: u32,
+) -> anyhow::Result<Vec<ContactView>> {
+    sqlx::query!(
+        "SELECT id FROM markers WHERE id = ? AND user_id = ?",
+        marker_id,
+        owner
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(ContactError::NotFound)?;
+
+    let views = sqlx::query_as!(
+        ContactView,
+        r#"
+        SELECT r.user_id, u.name as username, r.add_time as view_time
+        FROM contact_reveals as r
+        INNER JOIN users as u ON u.id = r.user_id
+        WHERE r.marker_id = ?
+        ORDER BY r.id DESC
+        "#,
+        marker_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(views)
+}
@@ -0,0 +1,104 @@
+use chrono::Utc;
+
+use super::{find_marker, FullMarkerOwned, MarkerError};
+
+/// Publishes a draft of the user right away, as if it had just been added
+pub async fn publish_draft(
+    db: &sqlx::MySqlPool,
+    user_id: u32,
+    marker_id: u32,
+) -> anyhow::Result<FullMarkerOwned> {
+    let mut tx = db.begin().await?;
+
+    let published = sqlx::query!(
+        r#"
+        UPDATE markers SET draft = FALSE, publish_at = NULL, add_time = ?
+        WHERE id = ? AND user_id = ? AND draft AND deleted_at IS NULL
+        "#,
+        Utc::now(),
+        marker_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+    if published.rows_affected() == 0 {
+        return Err(MarkerError::NotFound.into());
+    }
+
+    let marker = find_marker(&mut tx, marker_id, Some(user_id))
+        .await?
+        .ok_or(MarkerError::NotFound)?;
+
+    tx.commit().await?;
+
+    Ok(marker)
+}
+
+/// Publishes drafts whose publication time has come, returns how many of them were published.
+/// They count as added at that time, so listings show them among the newest markers
+pub async fn publish_scheduled_markers(db: &sqlx::MySqlPool) -> anyhow::Result<u64> {
+    // Assignments are applied left to right, `add_time` takes `publish_at` before it is cleared
+    let published = sqlx::query!(
+        r#"
+        UPDATE markers SET add_time = publish_at, draft = FALSE, publish_at = NULL
+        WHERE draft AND publish_at <= ? AND deleted_at IS NULL
+        "#,
+        Utc::now()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(published.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markers::{show_markers, MarkerFilter, PageRequest};
+    use crate::testing;
+    use chrono::Duration;
+    use sqlx::MySqlPool;
+
+    async fn listed(db: &MySqlPool) -> anyhow::Result<Vec<u64>> {
+        let page = show_markers(db, MarkerFilter::default(), PageRequest::first()).await?;
+        testing::ids(page)
+    }
+
+    #[sqlx::test]
+    async fn publishes_drafts(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let jan = testing::add_user(&db, "jan").await?;
+        let anna = testing::add_user(&db, "anna").await?;
+        let published = testing::add_marker(&db, jan, "Zakupy").await?;
+        let draft = testing::add_marker(&db, jan, "Szkic").await?;
+        let due = testing::add_marker(&db, jan, "Spacer").await?;
+        let scheduled = testing::add_marker(&db, jan, "Festyn").await?;
+        let now = Utc::now();
+        for (id, publish_at) in [
+            (draft, None),
+            (due, Some(now - Duration::minutes(1))),
+            (scheduled, Some(now + Duration::days(1))),
+        ] {
+            sqlx::query("UPDATE markers SET draft = TRUE, publish_at = ? WHERE id = ?")
+                .bind(publish_at)
+                .bind(id)
+                .execute(&db)
+                .await?;
+        }
+        assert_eq!(listed(&db).await?, [published as u64]);
+
+        assert_eq!(publish_scheduled_markers(&db).await?, 1);
+        // Published drafts count as added when they were due, older than the marker above
+        assert_eq!(listed(&db).await?, [published as u64, due as u64]);
+
+        assert!(publish_draft(&db, anna, draft).await.is_err());
+        assert!(publish_draft(&db, jan, published).await.is_err());
+        publish_draft(&db, jan, draft).await?;
+        assert_eq!(
+            listed(&db).await?,
+            [draft as u64, published as u64, due as u64]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use rocket::Responder;
+
+use super::FullMarkerOwned;
+
+const SITE_URL: &str = "https://cosiedzieje.mikut.dev";
+const TAG_AUTHORITY: &str = "cosiedzieje.mikut.dev,2022";
+
+#[derive(Responder)]
+#[response(content_type = "application/atom+xml")]
+pub struct AtomFeed(String);
+
+#[derive(Responder)]
+#[response(content_type = "application/rss+xml")]
+pub struct RssFeed(String);
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Keeps the city safe to put in URIs, only unreserved characters are left as they are
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Entry ids never change, even when the marker itself is edited
+fn entry_id(marker: &FullMarkerOwned) -> String {
+    format!("tag:{}:marker-{}", TAG_AUTHORITY, marker.id)
+}
+
+fn entry_link(marker: &FullMarkerOwned) -> String {
+    format!("{}/api/markers/id/{}", SITE_URL, marker.id)
+}
+
+fn feed_link(city: &str, format: &str) -> String {
+    format!(
+        "{}/api/markers/{}/{}",
+        SITE_URL,
+        percent_encode(city),
+        format
+    )
+}
+
+fn feed_title(city: &str) -> String {
+    format!("CoSięDzieje - {}", city)
+}
+
+/// The feed was last updated when any of its markers was last added or edited
+fn last_update(markers: &[FullMarkerOwned]) -> DateTime<Utc> {
+    markers
+        .iter()
+        .map(|marker| marker.update_time)
+        .max()
+        .unwrap_or_else(Utc::now)
+}
+
+impl AtomFeed {
+    pub fn from_markers(city: &str, markers: &[FullMarkerOwned]) -> Self {
+        let mut out = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        out.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        out.push_str(&format!(
+            "<id>tag:{}:city-{}</id>",
+            TAG_AUTHORITY,
+            percent_encode(city)
+        ));
+        out.push_str(&format!("<title>{}</title>", escape_xml(&feed_title(city))));
+        out.push_str(&format!(
+            "<updated>{}</updated>",
+            last_update(markers).to_rfc3339()
+        ));
+        out.push_str(&format!(r#"<link href="{}"/>"#, SITE_URL));
+        out.push_str(&format!(
+            r#"<link rel="self" href="{}"/>"#,
+            feed_link(city, "atom")
+        ));
+
+        for marker in markers {
+            out.push_str("<entry>");
+            out.push_str(&format!("<id>{}</id>", entry_id(marker)));
+            out.push_str(&format!("<title>{}</title>", escape_xml(&marker.title)));
+            out.push_str(&format!(
+                "<published>{}</published>",
+                marker.add_time.to_rfc3339()
+            ));
+            out.push_str(&format!(
+                "<updated>{}</updated>",
+                marker.update_time.to_rfc3339()
+            ));
+            out.push_str(&format!(
+                "<author><name>{}</name></author>",
+                escape_xml(&marker.username)
+            ));
+            out.push_str(&format!(
+                r#"<link rel="alternate" href="{}"/>"#,
+                entry_link(marker)
+            ));
+            out.push_str(&format!(
+                r#"<category term="{}"/>"#,
+                escape_xml(&marker.category.slug)
+            ));
+            out.push_str(&format!(
+                "<summary>{}</summary>",
+                escape_xml(&marker.description)
+            ));
+            out.push_str("</entry>");
+        }
+        out.push_str("</feed>");
+
+        Self(out)
+    }
+}
+
+impl RssFeed {
+    pub fn from_markers(city: &str, markers: &[FullMarkerOwned]) -> Self {
+        let mut out = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        out.push_str(r#"<rss version="2.0"><channel>"#);
+        out.push_str(&format!("<title>{}</title>", escape_xml(&feed_title(city))));
+        out.push_str(&format!("<link>{}</link>", SITE_URL));
+        out.push_str(&format!(
+            "<description>{}</description>",
+            escape_xml(&format!("Nowe wydarzenia w miejscowości {}", city))
+        ));
+        out.push_str("<language>pl</language>");
+        out.push_str(&format!(
+            "<lastBuildDate>{}</lastBuildDate>",
+            last_update(markers).to_rfc2822()
+        ));
+
+        for marker in markers {
+            out.push_str("<item>");
+            out.push_str(&format!("<title>{}</title>", escape_xml(&marker.title)));
+            out.push_str(&format!("<link>{}</link>", entry_link(marker)));
+            out.push_str(&format!(
+                "<description>{}</description>",
+                escape_xml(&marker.description)
+            ));
+            out.push_str(&format!(
+                "<category>{}</category>",
+                escape_xml(&marker.category.slug)
+            ));
+            out.push_str(&format!(
+                r#"<guid isPermaLink="false">{}</guid>"#,
+                entry_id(marker)
+            ));
+            out.push_str(&format!(
+                "<pubDate>{}</pubDate>",
+                marker.add_time.to_rfc2822()
+            ));
+            out.push_str("</item>");
+        }
+        out.push_str("</channel></rss>");
+
+        Self(out)
+    }
+}
@@ -0,0 +1,289 @@
+use chrono::{DateTime, TimeZone, Utc};
+use rocket::FromForm;
+use sqlx::{MySql, QueryBuilder};
+
+use super::{recurrence, EventType, FullMarkerOwned};
+
+/// Filters shared by every marker listing, parsed from the query string.
+/// All of them are optional and combined with `AND`
+#[derive(FromForm, Default)]
+pub struct MarkerFilter<'r> {
+    /// Slugs of categories, markers of their subcategories match as well
+    #[field(name = "category")]
+    categories: Vec<&'r str>,
+    /// Marker types of older clients, matched as the categories they stand for
+    #[field(name = "type")]
+    types: Vec<EventType>,
+    /// Start of the time window, markers ending before it are skipped
+    #[field(name = "startTime")]
+    start_time: Option<i64>,
+    /// End of the time window, markers starting after it are skipped
+    #[field(name = "endTime")]
+    end_time: Option<i64>,
+    /// Only markers that have not ended yet
+    upcoming: bool,
+    /// Include markers archived after they ended
+    archived: bool,
+    author: Option<u32>,
+    city: Option<&'r str>,
+    /// Latitude, longitude and radius in kilometres, used only when all three are given
+    lat: Option<f64>,
+    long: Option<f64>,
+    dist: Option<u32>,
+}
+
+/// Kilometres per degree of latitude
+const DISTANCE_UNIT: f64 = 111.045;
+
+fn from_timestamp(timestamp: Option<i64>) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(timestamp?, 0).single()
+}
+
+impl<'r> MarkerFilter<'r> {
+    pub fn in_city(mut self, city: &'r str) -> Self {
+        self.city = Some(city);
+        self
+    }
+
+    /// Authors see their whole history, archived markers included
+    pub fn by_author(mut self, user_id: u32) -> Self {
+        self.author = Some(user_id);
+        self.archived = true;
+        self
+    }
+
+    /// Whether the filter narrows markers down to a city or a radius
+    pub fn is_local(&self) -> bool {
+        self.city.is_some() || (self.lat.is_some() && self.long.is_some() && self.dist.is_some())
+    }
+
+    /// Appends the conditions to an already started `WHERE` clause, leaving out drafts and
+    /// markers hidden by moderation
+    pub(crate) fn push_conditions(&self, builder: &mut QueryBuilder<'r, MySql>) {
+        builder.push(" AND hidden_at IS NULL AND NOT draft");
+        self.push_conditions_with_hidden(builder);
+    }
+
+    /// Same as `push_conditions`, but keeps drafts and hidden markers. Meant for listings of their
+    /// authors.
+    /// Deleted markers are left out either way, they are only listed in the trash
+    pub(crate) fn push_conditions_with_hidden(&self, builder: &mut QueryBuilder<'r, MySql>) {
+        builder.push(" AND deleted_at IS NULL");
+        if !self.archived {
+            builder.push(" AND archived_at IS NULL");
+        }
+
+        if !self.categories.is_empty() || !self.types.is_empty() {
+            builder.push(
+                r#" AND category_id IN (
+                SELECT c.id FROM categories AS c
+                LEFT JOIN categories AS p ON p.id = c.parent_id
+                WHERE c.slug IN ("#,
+            );
+            self.push_slugs(builder);
+            builder.push(" OR p.slug IN (");
+            self.push_slugs(builder);
+            builder.push(")");
+        }
+
+        // Recurring markers are matched by their whole series here and narrowed down to
+        // single occurrences by `expand_occurrences`. Markers without an end are over once
+        // they start, only undated ones are kept
+        if let Some(start_time) = from_timestamp(self.start_time) {
+            builder
+                .push(" AND COALESCE(COALESCE(IF(recurrence IS NULL, end_time, series_end),")
+                .push(" start_time) >= ")
+                .push_bind(start_time)
+                .push(", TRUE)");
+        }
+
+        if let Some(end_time) = from_timestamp(self.end_time) {
+            builder
+                .push(" AND (start_time IS NULL OR start_time <= ")
+                .push_bind(end_time)
+                .push(")");
+        }
+
+        if self.upcoming {
+            let now = Utc::now();
+            builder
+                .push(" AND IF(recurrence IS NULL, COALESCE(end_time, start_time, ")
+                .push_bind(now)
+                .push(") >= ")
+                .push_bind(now)
+                .push(", COALESCE(series_end >= ")
+                .push_bind(now)
+                .push(", TRUE))");
+        }
+
+        if let Some(author) = self.author {
+            builder.push(" AND user_id = ").push_bind(author);
+        }
+
+        if let Some(city) = self.city {
+            builder
+                .push(r#" AND JSON_EXTRACT(address, "$.city") = "#)
+                .push_bind(city);
+        }
+
+        if let (Some(lat), Some(long), Some(dist)) = (self.lat, self.long, self.dist) {
+            push_within(builder, lat, long, dist);
+        }
+    }
+
+    /// Binds the requested slugs and closes the list they are in
+    fn push_slugs(&self, builder: &mut QueryBuilder<'r, MySql>) {
+        let mut slugs = builder.separated(", ");
+        for slug in &self.categories {
+            slugs.push_bind(*slug);
+        }
+        for r#type in &self.types {
+            slugs.push_bind(r#type.slug());
+        }
+        slugs.push_unseparated(")");
+    }
+}
+
+impl MarkerFilter<'_> {
+    /// Fills in the occurrences of recurring markers when a time window was requested
+    pub(crate) fn expand_occurrences<T: AsMut<FullMarkerOwned>>(&self, markers: &mut Vec<T>) {
+        let from = from_timestamp(self.start_time);
+        let to = from_timestamp(self.end_time);
+        if from.is_some() || to.is_some() {
+            recurrence::expand(markers, from, to);
+        }
+    }
+}
+
+/// Same formula as in `show_markers_by_dist`, written as a plain condition so it can be
+/// combined with queries that can't select from a derived table
+fn push_within(builder: &mut QueryBuilder<'_, MySql>, lat: f64, long: f64, dist: u32) {
+    let lat_delta = dist as f64 / DISTANCE_UNIT;
+    let long_delta = dist as f64 / (DISTANCE_UNIT * lat.to_radians().cos());
+
+    builder
+        .push(" AND latitude BETWEEN ")
+        .push_bind(lat - lat_delta)
+        .push(" AND ")
+        .push_bind(lat + lat_delta)
+        .push(" AND longitude BETWEEN ")
+        .push_bind(long - long_delta)
+        .push(" AND ")
+        .push_bind(long + long_delta)
+        .push(" AND ")
+        .push(DISTANCE_UNIT)
+        .push(" * DEGREES(ACOS(LEAST(1.0, COS(RADIANS(")
+        .push_bind(lat)
+        .push(")) * COS(RADIANS(latitude)) * COS(RADIANS(")
+        .push_bind(long)
+        .push(") - RADIANS(longitude)) + SIN(RADIANS(")
+        .push_bind(lat)
+        .push(")) * SIN(RADIANS(latitude))))) <= ")
+        .push_bind(dist);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markers::{show_markers, PageRequest};
+    use crate::testing;
+    use sqlx::MySqlPool;
+
+    async fn listed(db: &MySqlPool, filter: MarkerFilter<'_>) -> anyhow::Result<Vec<u64>> {
+        let page = show_markers(db, filter, PageRequest::new(None, None).unwrap()).await?;
+        testing::ids(page)
+    }
+
+    #[sqlx::test]
+    async fn filters_by_category_author_and_city(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let jan = testing::add_user(&db, "jan").await?;
+        let anna = testing::add_user(&db, "anna").await?;
+        let help = testing::add_marker(&db, jan, "Zakupy").await? as u64;
+        let charity = testing::add_marker(&db, anna, "Zbiórka").await? as u64;
+        let food_bank = sqlx::query(
+            r#"
+            INSERT INTO categories (slug, name_pl, name_en, parent_id)
+            VALUES ("food-bank", "Bank żywności", "Food bank", 3)
+            "#,
+        )
+        .execute(&db)
+        .await?
+        .last_insert_id();
+        sqlx::query(
+            r#"
+            UPDATE markers
+            SET category_id = ?, address = JSON_SET(address, "$.city", "Katowice")
+            WHERE id = ?
+            "#,
+        )
+        .bind(food_bank)
+        .bind(charity)
+        .execute(&db)
+        .await?;
+
+        assert_eq!(listed(&db, MarkerFilter::default()).await?, [charity, help]);
+        // Subcategories match the slug of their parent as well
+        for slug in ["charity", "food-bank"] {
+            let filter = MarkerFilter {
+                categories: vec![slug],
+                ..Default::default()
+            };
+            assert_eq!(listed(&db, filter).await?, [charity], "{}", slug);
+        }
+        let legacy = MarkerFilter {
+            types: vec![EventType::Charity],
+            ..Default::default()
+        };
+        assert_eq!(listed(&db, legacy).await?, [charity]);
+        let by_jan = MarkerFilter::default().by_author(jan);
+        assert_eq!(listed(&db, by_jan).await?, [help]);
+        let in_katowice = MarkerFilter::default().in_city("Katowice");
+        assert_eq!(listed(&db, in_katowice).await?, [charity]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn filters_by_time_window(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let jan = testing::add_user(&db, "jan").await?;
+        let undated = testing::add_marker(&db, jan, "Bez terminu").await? as u64;
+        let past = testing::add_marker(&db, jan, "Festyn").await? as u64;
+        let future = testing::add_marker(&db, jan, "Koncert").await? as u64;
+        let open_ended = testing::add_marker(&db, jan, "Zbiórka").await? as u64;
+        let dated = "UPDATE markers SET start_time = ?, end_time = ? WHERE id = ?";
+        for (id, start) in [(past, 1_600_000_000), (future, 1_900_000_000)] {
+            sqlx::query(dated)
+                .bind(from_timestamp(Some(start)))
+                .bind(from_timestamp(Some(start + 3600)))
+                .bind(id)
+                .execute(&db)
+                .await?;
+        }
+        sqlx::query("UPDATE markers SET start_time = ? WHERE id = ?")
+            .bind(from_timestamp(Some(1_600_000_000)))
+            .bind(open_ended)
+            .execute(&db)
+            .await?;
+
+        let window = MarkerFilter {
+            start_time: Some(1_700_000_000),
+            end_time: Some(2_000_000_000),
+            ..Default::default()
+        };
+        assert_eq!(listed(&db, window).await?, [future, undated]);
+        let before = MarkerFilter {
+            end_time: Some(1_700_000_000),
+            ..Default::default()
+        };
+        assert_eq!(listed(&db, before).await?, [open_ended, past, undated]);
+        let upcoming = MarkerFilter {
+            upcoming: true,
+            ..Default::default()
+        };
+        assert_eq!(listed(&db, upcoming).await?, [future, undated]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use rocket::request::{self, FromRequest};
+use rocket::serde::json::Json;
+use rocket::{Request, Responder};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{FullMarkerOwned, Page};
+use crate::{SomsiadResult, SomsiadStatus};
+
+/// Marker fields that either end up in the geometry or must not be published
+const SKIPPED_PROPERTIES: [&str; 4] = ["id", "latitude", "longitude", "contactInfo"];
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum Geometry {
+    Point { coordinates: [f64; 2] },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "Feature")]
+pub struct Feature {
+    id: u32,
+    geometry: Geometry,
+    properties: Value,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "FeatureCollection")]
+pub struct FeatureCollection {
+    features: Vec<Feature>,
+    /// Foreign member carrying the pagination cursor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+}
+
+#[derive(Responder)]
+#[response(content_type = "application/geo+json")]
+pub struct GeoJson(Json<FeatureCollection>);
+
+/// Whether the client asked for GeoJSON in the `Accept` header
+pub struct AcceptsGeoJson(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptsGeoJson {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let accepts = request.accept().map_or(false, |accept| {
+            let media_type = accept.preferred().media_type();
+            media_type.top() == "application" && media_type.sub() == "geo+json"
+        });
+
+        request::Outcome::Success(Self(accepts))
+    }
+}
+
+/// Marker listing in the format the client negotiated, the usual JSON unless it asked
+/// for GeoJSON
+#[derive(Responder)]
+pub enum Listing<T> {
+    Json(T),
+    GeoJson(GeoJson),
+}
+
+impl<T: Serialize + AsMut<FullMarkerOwned>> Listing<Page<T>> {
+    pub fn from_page(geojson: AcceptsGeoJson, page: Page<T>) -> serde_json::Result<Self> {
+        match geojson {
+            AcceptsGeoJson(true) => Ok(Self::GeoJson(GeoJson::from_page(page)?)),
+            AcceptsGeoJson(false) => Ok(Self::Json(page)),
+        }
+    }
+}
+
+impl<T: Serialize + AsMut<FullMarkerOwned>> Listing<SomsiadResult<Vec<T>>> {
+    pub fn from_markers(geojson: AcceptsGeoJson, markers: Vec<T>) -> serde_json::Result<Self> {
+        match geojson {
+            AcceptsGeoJson(true) => Ok(Self::GeoJson(GeoJson::from_markers(markers, None)?)),
+            AcceptsGeoJson(false) => Ok(Self::Json(SomsiadStatus::ok(markers))),
+        }
+    }
+}
+
+impl Feature {
+    /// Everything but the coordinates and private fields goes into the properties, the distance
+    /// of markers found nearby included
+    pub fn from_marker<T: Serialize + AsMut<FullMarkerOwned>>(
+        mut item: T,
+    ) -> serde_json::Result<Self> {
+        let mut properties = serde_json::to_value(&item)?;
+        if let Value::Object(map) = &mut properties {
+            for key in SKIPPED_PROPERTIES {
+                map.remove(key);
+            }
+        }
+        let marker = item.as_mut();
+
+        Ok(Self {
+            id: marker.id,
+            // GeoJSON puts longitude first
+            geometry: Geometry::Point {
+                coordinates: [marker.longitude, marker.latitude],
+            },
+            properties,
+        })
+    }
+}
+
+impl GeoJson {
+    pub fn from_markers<T: Serialize + AsMut<FullMarkerOwned>>(
+        markers: Vec<T>,
+        next: Option<String>,
+    ) -> serde_json::Result<Self> {
+        let features = markers
+            .into_iter()
+            .map(Feature::from_marker)
+            .collect::<serde_json::Result<_>>()?;
+
+        Ok(Self(Json(FeatureCollection { features, next })))
+    }
+
+    pub fn from_page<T: Serialize + AsMut<FullMarkerOwned>>(
+        page: Page<T>,
+    ) -> serde_json::Result<Self> {
+        let (markers, next) = page.into_parts();
+        Self::from_markers(markers, next)
+    }
+}
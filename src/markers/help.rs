@@ -0,0 +1,448 @@
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::mysql::{MySqlTypeInfo, MySqlValueRef};
+use sqlx::{MySql, Transaction};
+use std::fmt;
+
+use crate::users::reputation::{self, ReputationReason};
+
+#[derive(Debug)]
+pub enum HelpError {
+    NotFound,
+    NotHelpRequest,
+    OwnMarker,
+    NotAllowed,
+}
+
+impl fmt::Display for HelpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "marker not found"),
+            Self::NotHelpRequest => write!(f, "marker is not a neighbour help request"),
+            Self::OwnMarker => write!(f, "users can't claim their own help requests"),
+            Self::NotAllowed => write!(f, "transition not allowed in the current state"),
+        }
+    }
+}
+
+impl std::error::Error for HelpError {}
+
+/// Where a neighbour help request stands. Requests nobody touched yet are open
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HelpStatus {
+    Open,
+    /// Someone offered to help, waiting for the author
+    Claimed,
+    /// The author accepted the helper
+    Confirmed,
+    Done,
+}
+
+impl HelpStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Claimed => "claimed",
+            Self::Confirmed => "confirmed",
+            Self::Done => "done",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "open" => Some(Self::Open),
+            "claimed" => Some(Self::Claimed),
+            "confirmed" => Some(Self::Confirmed),
+            "done" => Some(Self::Done),
+            _ => None,
+        }
+    }
+}
+
+// Listings compute the status with `IF`, which turns the ENUM into a plain string the derived
+// impl would refuse. Any string column is accepted instead
+impl sqlx::Type<MySql> for HelpStatus {
+    fn type_info() -> MySqlTypeInfo {
+        <str as sqlx::Type<MySql>>::type_info()
+    }
+
+    fn compatible(ty: &MySqlTypeInfo) -> bool {
+        <str as sqlx::Type<MySql>>::compatible(ty)
+    }
+}
+
+impl sqlx::Encode<'_, MySql> for HelpStatus {
+    fn encode_by_ref(&self, buf: &mut Vec<u8>) -> IsNull {
+        <&str as sqlx::Encode<MySql>>::encode(self.as_str(), buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, MySql> for HelpStatus {
+    fn decode(value: MySqlValueRef<'r>) -> Result<Self, BoxDynError> {
+        let name = <&str as sqlx::Decode<MySql>>::decode(value)?;
+        Self::from_name(name).ok_or_else(|| format!("unknown help status {}", name).into())
+    }
+}
+
+pub enum HelpAction {
+    /// Offer to help, done by anyone but the author
+    Claim,
+    /// Accept the helper, done by the author
+    Confirm,
+    /// Mark the help as given, done by the author
+    Done,
+    /// Drop the claim, done by the helper or the author
+    Release,
+}
+
+#[derive(Serialize)]
+pub struct HelpState {
+    status: HelpStatus,
+    #[serde(rename = "helperID")]
+    helper_id: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct HelpTransition {
+    #[serde(rename = "userID")]
+    user_id: i32,
+    username: String,
+    #[serde(rename = "helperID")]
+    helper_id: Option<i32>,
+    from: HelpStatus,
+    to: HelpStatus,
+    #[serde(with = "ts_seconds")]
+    #[serde(rename = "addTime")]
+    add_time: DateTime<Utc>,
+}
+
+impl HelpAction {
+    /// New state of the request, if `user_id` may take the action in the current one
+    fn apply(
+        &self,
+        user_id: u32,
+        author: u32,
+        current: &HelpState,
+    ) -> Result<HelpState, HelpError> {
+        let is_author = user_id == author;
+        let (status, helper_id) = match (self, current.status) {
+            (Self::Claim, _) if is_author => return Err(HelpError::OwnMarker),
+            (Self::Claim, HelpStatus::Open) => (HelpStatus::Claimed, Some(user_id)),
+            (Self::Confirm, HelpStatus::Claimed) if is_author => {
+                (HelpStatus::Confirmed, current.helper_id)
+            }
+            (Self::Done, HelpStatus::Confirmed) if is_author => {
+                (HelpStatus::Done, current.helper_id)
+            }
+            (Self::Release, HelpStatus::Claimed | HelpStatus::Confirmed)
+                if is_author || current.helper_id == Some(user_id) =>
+            {
+                (HelpStatus::Open, None)
+            }
+            _ => return Err(HelpError::NotAllowed),
+        };
+
+        Ok(HelpState { status, helper_id })
+    }
+
+    /// Moves the request along, the helper gains reputation once it is done
+    pub async fn perform(
+        &self,
+        db: &sqlx::MySqlPool,
+        user_id: u32,
+        marker_id: u32,
+    ) -> anyhow::Result<HelpState> {
+        let mut tx = db.begin().await?;
+
+        let marker = sqlx::query!(
+            r#"
+            SELECT user_id,
+            (SELECT help FROM categories WHERE categories.id = markers.category_id)
+                as `help!: bool`
+            FROM markers
+            WHERE id = ? AND archived_at IS NULL AND hidden_at IS NULL AND deleted_at IS NULL
+            AND NOT draft
+            FOR UPDATE
+            "#,
+            marker_id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(HelpError::NotFound)?;
+        if !marker.help {
+            return Err(HelpError::NotHelpRequest.into());
+        }
+
+        let current = current_state(&mut tx, marker_id).await?;
+        let next = self.apply(user_id, marker.user_id as u32, &current)?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO help_requests (marker_id, status, helper_id) VALUES (?, ?, ?)
+            ON DUPLICATE KEY UPDATE status = VALUES(status), helper_id = VALUES(helper_id)
+            "#,
+            marker_id,
+            next.status,
+            next.helper_id
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO help_transitions (marker_id, user_id, helper_id, from_status, to_status)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+            marker_id,
+            user_id,
+            current.helper_id.or(next.helper_id),
+            current.status,
+            next.status
+        )
+        .execute(&mut tx)
+        .await?;
+
+        if let (HelpStatus::Done, Some(helper_id)) = (next.status, next.helper_id) {
+            reputation::record(
+                &mut tx,
+                helper_id,
+                ReputationReason::Help,
+                Some(marker_id),
+                Some(user_id),
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(next)
+    }
+}
+
+async fn current_state(
+    tx: &mut Transaction<'_, MySql>,
+    marker_id: u32,
+) -> anyhow::Result<HelpState> {
+    let state = sqlx::query!(
+        r#"
+        SELECT status as `status: HelpStatus`, helper_id
+        FROM help_requests
+        WHERE marker_id = ?
+        "#,
+        marker_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .map_or(
+        HelpState {
+            status: HelpStatus::Open,
+            helper_id: None,
+        },
+        |state| HelpState {
+            status: state.status,
+            helper_id: state.helper_id.map(|id| id as u32),
+        },
+    );
+
+    Ok(state)
+}
+
+/// Every change of the state of the request, oldest first
+pub async fn show_help_history(
+    db: &sqlx::MySqlPool,
+    marker_id: u32,
+) -> anyhow::Result<Vec<HelpTransition>> {
+    sqlx::query!(
+        r#"
+        SELECT id FROM markers
+        WHERE id = ? AND hidden_at IS NULL AND deleted_at IS NULL AND NOT draft
+        "#,
+        marker_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(HelpError::NotFound)?;
+
+    let transitions = sqlx::query_as!(
+        HelpTransition,
+        r#"
+        SELECT t.user_id, u.name as username, t.helper_id,
+        t.from_status as `from: HelpStatus`, t.to_status as `to: HelpStatus`, t.add_time
+        FROM help_transitions as t
+        INNER JOIN users as u ON u.id = t.user_id
+        WHERE t.marker_id = ?
+        ORDER BY t.id
+        "#,
+        marker_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(transitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markers::show_marker;
+    use crate::testing;
+    use sqlx::MySqlPool;
+
+    const AUTHOR: u32 = 1;
+    const HELPER: u32 = 2;
+    const OTHER: u32 = 3;
+
+    fn apply(
+        action: &HelpAction,
+        user_id: u32,
+        status: HelpStatus,
+        helper_id: Option<u32>,
+    ) -> Result<(HelpStatus, Option<u32>), HelpError> {
+        action
+            .apply(user_id, AUTHOR, &HelpState { status, helper_id })
+            .map(|state| (state.status, state.helper_id))
+    }
+
+    #[test]
+    fn allowed_transitions() {
+        use HelpStatus::*;
+        let transitions = [
+            (HelpAction::Claim, HELPER, Open, None, Claimed, Some(HELPER)),
+            (
+                HelpAction::Confirm,
+                AUTHOR,
+                Claimed,
+                Some(HELPER),
+                Confirmed,
+                Some(HELPER),
+            ),
+            (
+                HelpAction::Done,
+                AUTHOR,
+                Confirmed,
+                Some(HELPER),
+                Done,
+                Some(HELPER),
+            ),
+            (
+                HelpAction::Release,
+                HELPER,
+                Claimed,
+                Some(HELPER),
+                Open,
+                None,
+            ),
+            (
+                HelpAction::Release,
+                HELPER,
+                Confirmed,
+                Some(HELPER),
+                Open,
+                None,
+            ),
+            (
+                HelpAction::Release,
+                AUTHOR,
+                Claimed,
+                Some(HELPER),
+                Open,
+                None,
+            ),
+        ];
+        for (action, user_id, from, helper_id, to, new_helper_id) in transitions {
+            let next = apply(&action, user_id, from, helper_id).unwrap();
+            assert_eq!(next, (to, new_helper_id), "{:?} by {}", from, user_id);
+        }
+    }
+
+    #[test]
+    fn authors_cant_claim_their_own_requests() {
+        let claimed = apply(&HelpAction::Claim, AUTHOR, HelpStatus::Open, None);
+        assert!(matches!(claimed, Err(HelpError::OwnMarker)));
+    }
+
+    #[test]
+    fn rejected_transitions() {
+        use HelpStatus::*;
+        let transitions = [
+            (HelpAction::Claim, OTHER, Claimed, Some(HELPER)),
+            (HelpAction::Confirm, HELPER, Claimed, Some(HELPER)),
+            (HelpAction::Confirm, AUTHOR, Open, None),
+            (HelpAction::Done, AUTHOR, Claimed, Some(HELPER)),
+            (HelpAction::Done, HELPER, Confirmed, Some(HELPER)),
+            (HelpAction::Release, OTHER, Claimed, Some(HELPER)),
+            (HelpAction::Release, AUTHOR, Open, None),
+            (HelpAction::Release, HELPER, Done, Some(HELPER)),
+        ];
+        for (action, user_id, from, helper_id) in transitions {
+            let next = apply(&action, user_id, from, helper_id);
+            assert!(
+                matches!(next, Err(HelpError::NotAllowed)),
+                "{:?} by {}",
+                from,
+                user_id
+            );
+        }
+    }
+
+    async fn help_state(
+        db: &MySqlPool,
+        marker_id: u32,
+    ) -> anyhow::Result<(Option<HelpStatus>, Option<i32>)> {
+        let marker = show_marker(db, marker_id)
+            .await?
+            .ok_or(HelpError::NotFound)?;
+        Ok((marker.help_status, marker.helper_id))
+    }
+
+    #[sqlx::test]
+    async fn lists_the_state_of_help_requests(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let jan = testing::add_user(&db, "jan").await?;
+        let anna = testing::add_user(&db, "anna").await?;
+        let request = testing::add_marker(&db, jan, "Zakupy").await?;
+        let happening = testing::add_marker(&db, jan, "Koncert").await?;
+        sqlx::query(r#"UPDATE markers SET category_id = 2 WHERE id = ?"#)
+            .bind(happening)
+            .execute(&db)
+            .await?;
+
+        assert_eq!(
+            help_state(&db, request).await?,
+            (Some(HelpStatus::Open), None)
+        );
+        assert_eq!(help_state(&db, happening).await?, (None, None));
+        let claimed = HelpAction::Claim.perform(&db, anna, happening).await;
+        let error = claimed.err().and_then(|e| e.downcast::<HelpError>().ok());
+        assert!(matches!(error, Some(HelpError::NotHelpRequest)));
+
+        HelpAction::Claim.perform(&db, anna, request).await?;
+        let helper = Some(anna as i32);
+        assert_eq!(
+            help_state(&db, request).await?,
+            (Some(HelpStatus::Claimed), helper)
+        );
+        HelpAction::Confirm.perform(&db, jan, request).await?;
+        HelpAction::Done.perform(&db, jan, request).await?;
+        assert_eq!(
+            help_state(&db, request).await?,
+            (Some(HelpStatus::Done), helper)
+        );
+
+        let history = show_help_history(&db, request).await?;
+        let steps: Vec<_> = history.iter().map(|t| (t.from, t.to)).collect();
+        assert_eq!(
+            steps,
+            [
+                (HelpStatus::Open, HelpStatus::Claimed),
+                (HelpStatus::Claimed, HelpStatus::Confirmed),
+                (HelpStatus::Confirmed, HelpStatus::Done),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use chrono::{DateTime, Utc};
+use rocket::request::FromParam;
+use rocket::Responder;
+
+use super::FullMarkerOwned;
+
+const PRODUCT_ID: &str = "-//CoSięDzieje//somsiad-api//PL";
+const UID_DOMAIN: &str = "cosiedzieje.mikut.dev";
+/// RFC 5545 recommends folding lines longer than 75 octets
+const MAX_LINE_LEN: usize = 75;
+
+#[derive(Responder)]
+#[response(content_type = "text/calendar")]
+pub struct Calendar(String);
+
+/// Path segment in the form of `<id>.ics`
+pub struct IcsFile(pub u32);
+
+impl<'a> FromParam<'a> for IcsFile {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        param
+            .strip_suffix(".ics")
+            .and_then(|id| id.parse().ok())
+            .map(Self)
+            .ok_or(param)
+    }
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Appends a content line, folding it without splitting multi-byte characters
+fn push_line(out: &mut String, line: &str) {
+    let mut line_len = 0;
+    for c in line.chars() {
+        if line_len + c.len_utf8() > MAX_LINE_LEN {
+            out.push_str("\r\n ");
+            // The leading space counts towards the length of the continuation line
+            line_len = 1;
+        }
+        out.push(c);
+        line_len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// Markers without a start time aren't events a calendar could show, so they are skipped
+fn push_event(out: &mut String, marker: &FullMarkerOwned) {
+    let start_time = match &marker.start_time {
+        Some(start_time) => start_time,
+        None => return,
+    };
+
+    push_line(out, "BEGIN:VEVENT");
+    push_line(out, &format!("UID:marker-{}@{}", marker.id, UID_DOMAIN));
+    // Calendars compare stamps to tell whether an event changed since they last fetched it
+    push_line(
+        out,
+        &format!("DTSTAMP:{}", format_time(&marker.update_time)),
+    );
+    push_line(out, &format!("DTSTART:{}", format_time(start_time)));
+    if let Some(end_time) = &marker.end_time {
+        push_line(out, &format!("DTEND:{}", format_time(end_time)));
+    }
+    if let Some(recurrence) = &marker.recurrence {
+        push_line(out, &format!("RRULE:{}", recurrence.to_rrule()));
+        if let Some(exdate) = recurrence.to_exdate() {
+            push_line(out, &format!("EXDATE:{}", exdate));
+        }
+    }
+    push_line(out, &format!("SUMMARY:{}", escape_text(&marker.title)));
+    push_line(
+        out,
+        &format!("DESCRIPTION:{}", escape_text(&marker.description)),
+    );
+    push_line(
+        out,
+        &format!("LOCATION:{}", escape_text(&marker.address.0.to_string())),
+    );
+    push_line(
+        out,
+        &format!("GEO:{};{}", marker.latitude, marker.longitude),
+    );
+    push_line(out, "END:VEVENT");
+}
+
+impl Calendar {
+    pub fn from_markers(name: &str, markers: &[FullMarkerOwned]) -> Self {
+        let mut out = String::new();
+        push_line(&mut out, "BEGIN:VCALENDAR");
+        push_line(&mut out, "VERSION:2.0");
+        push_line(&mut out, &format!("PRODID:{}", PRODUCT_ID));
+        push_line(&mut out, "CALSCALE:GREGORIAN");
+        push_line(&mut out, &format!("X-WR-CALNAME:{}", escape_text(name)));
+        for marker in markers {
+            push_event(&mut out, marker);
+        }
+        push_line(&mut out, "END:VCALENDAR");
+
+        Self(out)
+    }
+
+    /// `None` when the marker has no start time
+    pub fn from_marker(marker: &FullMarkerOwned) -> Option<Self> {
+        marker.start_time?;
+        let name = format!("CoSięDzieje - {}", marker.title);
+
+        Some(Self::from_markers(&name, std::slice::from_ref(marker)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folded(line: &str) -> String {
+        let mut out = String::new();
+        push_line(&mut out, line);
+        out
+    }
+
+    fn line_lengths(out: &str) -> Vec<usize> {
+        out.trim_end_matches("\r\n")
+            .split("\r\n")
+            .map(str::len)
+            .collect()
+    }
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(escape_text("a;b,c\\d\r\ne"), r"a\;b\,c\\d\ne");
+    }
+
+    #[test]
+    fn keeps_short_lines() {
+        assert_eq!(folded("SUMMARY:x"), "SUMMARY:x\r\n");
+    }
+
+    #[test]
+    fn folds_long_lines() {
+        let line = "X".repeat(160);
+        let out = folded(&line);
+        assert_eq!(line_lengths(&out), [75, 75, 12]);
+        assert_eq!(out.replace("\r\n ", ""), format!("{}\r\n", line));
+    }
+
+    #[test]
+    fn folds_between_multi_byte_characters() {
+        let line = "ż".repeat(50);
+        let out = folded(&line);
+        assert_eq!(line_lengths(&out), [74, 27]);
+        assert_eq!(out.replace("\r\n ", ""), format!("{}\r\n", line));
+    }
+
+    #[test]
+    fn parses_ics_file_names() {
+        assert_eq!(IcsFile::from_param("12.ics").map(|file| file.0), Ok(12));
+        assert!(IcsFile::from_param("12").is_err());
+        assert!(IcsFile::from_param("x.ics").is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::io::{Limits, Reader};
+use image::ImageFormat;
+use nanoid::nanoid;
+use rocket::fs::{relative, TempFile};
+use rocket::tokio::{fs, task};
+use rocket::{error, FromForm};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+/// Uploaded files live next to `static/` and are served under `UPLOADS_URL` as long as
+/// their marker may be seen
+pub const UPLOADS_DIR: &str = relative!("uploads");
+pub const UPLOADS_URL: &str = "/uploads";
+/// Uploads are checked here before being moved into `UPLOADS_DIR`, it's never served
+pub const PENDING_UPLOADS_DIR: &str = relative!("uploads_pending");
+pub const MAX_IMAGE_SIZE: u64 = 5 * 1024 * 1024;
+const MAX_IMAGES: i64 = 8;
+/// Larger images are rejected before being decoded
+const MAX_DIMENSION: u32 = 8000;
+/// Name and longest side in pixels of every thumbnail
+const THUMBNAIL_SIZES: [(&str, u32); 2] = [("small", 200), ("medium", 800)];
+const THUMBNAIL_QUALITY: u8 = 80;
+
+#[derive(Debug)]
+pub enum ImageError {
+    NotFound,
+    TooLarge,
+    TooMany,
+    Unsupported,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "marker or image not found"),
+            Self::TooLarge => write!(f, "image is too large"),
+            Self::TooMany => write!(f, "marker has too many images"),
+            Self::Unsupported => write!(f, "file is not a supported image"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+#[derive(FromForm)]
+pub struct ImageUpload<'r> {
+    image: TempFile<'r>,
+}
+
+/// Image as stored in the database, serialized as the URLs it can be fetched from
+#[derive(Deserialize)]
+pub struct ImageFile {
+    id: u32,
+    marker_id: u32,
+    name: String,
+}
+
+#[derive(Serialize)]
+struct ImageUrls {
+    id: u32,
+    url: String,
+    thumbnails: BTreeMap<&'static str, String>,
+}
+
+impl Serialize for ImageFile {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let base = format!("{}/markers/{}", UPLOADS_URL, self.marker_id);
+        ImageUrls {
+            id: self.id,
+            url: format!("{}/{}", base, self.name),
+            thumbnails: THUMBNAIL_SIZES
+                .iter()
+                .map(|(size, _)| {
+                    (
+                        *size,
+                        format!("{}/{}", base, thumbnail_name(&self.name, size)),
+                    )
+                })
+                .collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+fn marker_dir(marker_id: u32) -> PathBuf {
+    Path::new(UPLOADS_DIR)
+        .join("markers")
+        .join(marker_id.to_string())
+}
+
+fn thumbnail_name(name: &str, size: &str) -> String {
+    let stem = name.split('.').next().unwrap_or(name);
+    format!("{}_{}.jpg", stem, size)
+}
+
+fn extension(format: ImageFormat) -> Option<&'static str> {
+    match format {
+        ImageFormat::Jpeg => Some("jpg"),
+        ImageFormat::Png => Some("png"),
+        ImageFormat::WebP => Some("webp"),
+        ImageFormat::Gif => Some("gif"),
+        _ => None,
+    }
+}
+
+/// Checks what the upload really is, regardless of the declared content type, and writes
+/// the image together with its thumbnails. Returns the name of the image
+fn store_image(dir: &Path, stem: &str, upload: &Path) -> anyhow::Result<String> {
+    let bytes = std::fs::read(upload)?;
+    let format = image::guess_format(&bytes).map_err(|_| ImageError::Unsupported)?;
+    let extension = extension(format).ok_or(ImageError::Unsupported)?;
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    let mut reader = Reader::with_format(Cursor::new(&bytes), format);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|_| ImageError::Unsupported)?;
+
+    let name = format!("{}.{}", stem, extension);
+    if let Err(e) = write_image(dir, &name, &image, upload) {
+        // The upload itself is removed by the caller
+        for (size, _) in THUMBNAIL_SIZES {
+            let _ = std::fs::remove_file(dir.join(thumbnail_name(&name, size)));
+        }
+        return Err(e);
+    }
+
+    Ok(name)
+}
+
+fn write_image(
+    dir: &Path,
+    name: &str,
+    image: &image::DynamicImage,
+    upload: &Path,
+) -> anyhow::Result<()> {
+    for (size, side) in THUMBNAIL_SIZES {
+        let thumbnail = image.thumbnail(side, side).to_rgb8();
+        let mut file = std::fs::File::create(dir.join(thumbnail_name(name, size)))?;
+        JpegEncoder::new_with_quality(&mut file, THUMBNAIL_QUALITY).encode_image(&thumbnail)?;
+    }
+    std::fs::rename(upload, dir.join(name))?;
+
+    Ok(())
+}
+
+async fn remove_image_files(marker_id: u32, name: &str) {
+    let dir = marker_dir(marker_id);
+    let thumbnails = THUMBNAIL_SIZES
+        .iter()
+        .map(|(size, _)| thumbnail_name(name, size));
+    for file in std::iter::once(name.to_string()).chain(thumbnails) {
+        if let Err(e) = fs::remove_file(dir.join(&file)).await {
+            if e.kind() != io::ErrorKind::NotFound {
+                error!("Failed to remove {}: {}", file, e);
+            }
+        }
+    }
+}
+
+/// Removes every file of the marker, called once the marker itself is gone
+pub async fn remove_marker_images(marker_id: u32) {
+    if let Err(e) = fs::remove_dir_all(marker_dir(marker_id)).await {
+        if e.kind() != io::ErrorKind::NotFound {
+            error!("Failed to remove images of marker {}: {}", marker_id, e);
+        }
+    }
+}
+
+impl ImageUpload<'_> {
+    pub async fn add_image(
+        &mut self,
+        db: &sqlx::MySqlPool,
+        user_id: u32,
+        marker_id: u32,
+    ) -> anyhow::Result<ImageFile> {
+        if self.image.len() > MAX_IMAGE_SIZE {
+            return Err(ImageError::TooLarge.into());
+        }
+
+        // Checked again once the image is stored, this only saves decoding uploads that
+        // would be rejected anyway
+        let images = sqlx::query!(
+            r#"
+            SELECT COUNT(i.id) as images
+            FROM markers as m
+            LEFT JOIN marker_images as i ON i.marker_id = m.id
+            WHERE m.id = ? AND m.user_id = ? AND m.deleted_at IS NULL
+            GROUP BY m.id
+            "#,
+            marker_id,
+            user_id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or(ImageError::NotFound)?
+        .images;
+        if images >= MAX_IMAGES {
+            return Err(ImageError::TooMany.into());
+        }
+
+        // Decoding and thumbnailing take a while, so they happen before the marker is locked
+        let dir = marker_dir(marker_id);
+        fs::create_dir_all(&dir).await?;
+        let stem = nanoid!(16);
+        let upload = Path::new(PENDING_UPLOADS_DIR).join(format!("{}.upload", stem));
+        self.image.move_copy_to(&upload).await?;
+
+        let stored = {
+            let (dir, upload) = (dir.clone(), upload.clone());
+            task::spawn_blocking(move || store_image(&dir, &stem, &upload)).await?
+        };
+        let name = match stored {
+            Ok(name) => name,
+            Err(e) => {
+                let _ = fs::remove_file(&upload).await;
+                return Err(e);
+            }
+        };
+
+        match insert_image(db, user_id, marker_id, &name).await {
+            Ok(id) => Ok(ImageFile {
+                id,
+                marker_id,
+                name,
+            }),
+            Err(e) => {
+                remove_image_files(marker_id, &name).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The marker row is locked only for the count and the insert, so concurrent uploads
+/// can't get past the limit
+async fn insert_image(
+    db: &sqlx::MySqlPool,
+    user_id: u32,
+    marker_id: u32,
+    name: &str,
+) -> anyhow::Result<u32> {
+    let mut tx = db.begin().await?;
+    sqlx::query!(
+        "SELECT id FROM markers WHERE id = ? AND user_id = ? AND deleted_at IS NULL FOR UPDATE",
+        marker_id,
+        user_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(ImageError::NotFound)?;
+
+    let images = sqlx::query!(
+        "SELECT COUNT(*) as images FROM marker_images WHERE marker_id = ?",
+        marker_id
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .images;
+    if images >= MAX_IMAGES {
+        return Err(ImageError::TooMany.into());
+    }
+
+    let added = sqlx::query!(
+        "INSERT INTO marker_images (marker_id, file_name) VALUES (?, ?)",
+        marker_id,
+        name
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(added.last_insert_id() as u32)
+}
+
+/// Path of an image or thumbnail of a marker the viewer may see. Hidden markers and drafts
+/// are only visible to their author and trashed ones to nobody, names that aren't stored
+/// are never resolved
+pub async fn find_image_file(
+    db: &sqlx::MySqlPool,
+    viewer_id: Option<u32>,
+    marker_id: u32,
+    file: &str,
+) -> anyhow::Result<PathBuf> {
+    let names = sqlx::query!(
+        r#"
+        SELECT i.file_name
+        FROM marker_images as i
+        INNER JOIN markers as m ON m.id = i.marker_id
+        WHERE i.marker_id = ? AND m.deleted_at IS NULL
+            AND ((m.hidden_at IS NULL AND NOT m.draft) OR m.user_id = ?)
+        "#,
+        marker_id,
+        viewer_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    names
+        .iter()
+        .any(|image| {
+            image.file_name == file
+                || THUMBNAIL_SIZES
+                    .iter()
+                    .any(|(size, _)| thumbnail_name(&image.file_name, size) == file)
+        })
+        .then(|| marker_dir(marker_id).join(file))
+        .ok_or_else(|| ImageError::NotFound.into())
+}
+
+/// Only the author of the marker may remove its images
+pub async fn delete_image(db: &sqlx::MySqlPool, user_id: u32, image_id: u32) -> anyhow::Result<()> {
+    let image = sqlx::query!(
+        r#"
+        SELECT i.marker_id, i.file_name
+        FROM marker_images as i
+        INNER JOIN markers as m ON m.id = i.marker_id
+        WHERE i.id = ? AND m.user_id = ?
+        "#,
+        image_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(ImageError::NotFound)?;
+
+    sqlx::query!("DELETE FROM marker_images WHERE id = ?", image_id)
+        .execute(db)
+        .await?;
+    remove_image_files(image.marker_id, &image.file_name).await;
+
+    Ok(())
+}
@@ -2,23 +2,84 @@ use chrono::serde::{ts_seconds, ts_seconds_option};
 use chrono::DateTime;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use sqlx::QueryBuilder;
+use std::fmt;
 pub use validator::Validate;
+use validator::ValidationError;
 
+use crate::mail::Mailer;
 use crate::users::login::AddressOwned;
 use crate::users::register::Address;
+pub use attendance::{
+    leave_marker, show_attendees, show_user_waitlist, waitlist_position, AttendanceError,
+    AttendanceRequest, Attendee, Signup, WaitlistEntry,
+};
+pub use bbox::BoundingBox;
+pub use categories::{
+    delete_category, show_categories, Category, CategoryError, CategoryUpdate, EventType,
+    MarkerCategory, NewCategory,
+};
+pub use cluster::{cluster_markers, Clusters, MAX_CLUSTER_SPAN};
+pub use comments::{delete_comment, show_comments, CommentError, NewComment, Thread};
+pub use contact::{
+    reveal_contact_info, show_contact_views, ContactError, ContactView, DEFAULT_REVEAL_LIMIT,
+};
+pub use drafts::publish_draft;
+pub use feed::{AtomFeed, RssFeed};
+pub use filter::MarkerFilter;
+pub use geojson::{AcceptsGeoJson, GeoJson, Listing};
+pub use help::{show_help_history, HelpAction, HelpError, HelpState, HelpStatus, HelpTransition};
+pub use ical::{Calendar, IcsFile};
+pub use images::{
+    delete_image, find_image_file, ImageError, ImageFile, ImageUpload, MAX_IMAGE_SIZE,
+};
+use pagination::{Cursor, Paginated};
+pub use pagination::{Page, PageRequest};
+pub use recurrence::{Occurrence, Recurrence};
+pub use revisions::{is_author, rollback_revision, show_history, Revision, RevisionError};
+pub use trash::{restore_deleted_marker, show_trash};
 
-#[derive(sqlx::Type, Serialize, Deserialize)]
-enum EventType {
-    #[sqlx(rename = "A")]
-    NeighborHelp,
-    #[sqlx(rename = "B")]
-    Happening,
-    #[sqlx(rename = "C")]
-    Charity,
-    #[sqlx(rename = "D")]
-    MassEvent,
+pub mod archive;
+pub mod attendance;
+pub mod bbox;
+pub mod categories;
+pub mod cluster;
+pub mod comments;
+pub mod contact;
+pub mod drafts;
+pub mod feed;
+pub mod filter;
+pub mod geojson;
+pub mod help;
+pub mod ical;
+pub mod images;
+pub mod nullable;
+pub mod pagination;
+pub mod recurrence;
+pub mod revisions;
+pub mod trash;
+
+#[derive(Debug)]
+pub enum MarkerError {
+    NotFound,
+    EndBeforeStart,
+    RecurrenceWithoutStart,
+    UnknownCategory,
+}
+
+impl fmt::Display for MarkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "marker not found"),
+            Self::EndBeforeStart => write!(f, "marker ends before it starts"),
+            Self::RecurrenceWithoutStart => write!(f, "recurring marker has no start time"),
+            Self::UnknownCategory => write!(f, "category not found"),
+        }
+    }
 }
 
+impl std::error::Error for MarkerError {}
+
 #[derive(Serialize, Deserialize /* , sqlx::Type */)]
 #[serde(tag = "type", content = "val")]
 enum ContactMethod {
@@ -26,22 +87,37 @@ enum ContactMethod {
     PhoneNumber(String),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Validate)]
 pub struct ContactInfo {
+    #[validate(length(min = 1))]
     name: String,
+    #[validate(length(min = 1))]
     surname: String,
+    #[validate]
     address: AddressOwned,
+    #[validate(custom = "validate_contact_method")]
     method: ContactMethod,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Validate)]
+#[validate(schema(function = "validate_times"))]
 pub struct FullMarker<'r> {
+    #[validate(range(min = -90.0, max = 90.0))]
     latitude: f64,
+    #[validate(range(min = -180.0, max = 180.0))]
     longitude: f64,
+    #[validate(length(min = 1, max = 25))]
     title: &'r str,
+    #[validate(length(min = 1, max = 65535))]
     description: &'r str,
+    #[serde(rename = "categoryID")]
+    #[serde(default)]
+    category_id: Option<u32>,
+    /// Sent by older clients instead of the category
     #[serde(rename = "type")]
-    r#type: EventType,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r#type: Option<EventType>,
     #[serde(with = "ts_seconds")]
     #[serde(rename = "addTime")]
     #[serde(default)]
@@ -54,52 +130,95 @@ pub struct FullMarker<'r> {
     #[serde(rename = "endTime")]
     #[serde(default)]
     end_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    #[validate]
+    recurrence: Option<Recurrence>,
+    /// Upper bound of people going, `None` means there is none
+    #[serde(default)]
+    #[validate(range(min = 1))]
+    capacity: Option<u32>,
+    #[validate]
     address: Address<'r>,
     #[serde(rename = "contactInfo")]
+    #[validate]
     contact_info: ContactInfo,
+    /// Drafts are seen by their author only until they get published
+    #[serde(default)]
+    draft: bool,
+    /// When a draft gets published on its own, setting it makes the marker a draft
+    #[serde(with = "ts_seconds_option")]
+    #[serde(rename = "publishAt")]
+    #[serde(default)]
+    publish_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct FullMarkerOwned {
-    id: u32,
-    latitude: f64,
-    longitude: f64,
-    title: String,
-    description: String,
+/// Partial update of a marker, every field left out of the request stays untouched.
+/// Optional fields are cleared by sending `null`
+#[derive(Deserialize, Validate)]
+pub struct MarkerUpdate<'r> {
+    #[validate(range(min = -90.0, max = 90.0))]
+    latitude: Option<f64>,
+    #[validate(range(min = -180.0, max = 180.0))]
+    longitude: Option<f64>,
+    #[validate(length(min = 1, max = 25))]
+    title: Option<&'r str>,
+    #[validate(length(min = 1, max = 65535))]
+    description: Option<&'r str>,
+    #[serde(rename = "categoryID")]
+    category_id: Option<u32>,
+    /// Sent by older clients instead of the category
     #[serde(rename = "type")]
-    r#type: EventType,
-    #[serde(with = "ts_seconds")]
-    #[serde(rename = "addTime")]
-    #[serde(default)]
-    add_time: DateTime<Utc>,
-    #[serde(with = "ts_seconds_option")]
+    r#type: Option<EventType>,
+    #[serde(deserialize_with = "nullable::timestamp")]
     #[serde(rename = "startTime")]
     #[serde(default)]
-    start_time: Option<DateTime<Utc>>,
-    #[serde(with = "ts_seconds_option")]
+    start_time: Option<Option<DateTime<Utc>>>,
+    #[serde(deserialize_with = "nullable::timestamp")]
     #[serde(rename = "endTime")]
     #[serde(default)]
-    end_time: Option<DateTime<Utc>>,
-    address: sqlx::types::Json<AddressOwned>,
+    end_time: Option<Option<DateTime<Utc>>>,
+    #[serde(deserialize_with = "nullable::deserialize")]
+    #[serde(default)]
+    #[validate]
+    recurrence: Option<Option<Recurrence>>,
+    #[serde(deserialize_with = "nullable::deserialize")]
+    #[serde(default)]
+    #[validate(range(min = 1))]
+    capacity: Option<Option<u32>>,
+    #[validate]
+    address: Option<Address<'r>>,
     #[serde(rename = "contactInfo")]
-    contact_info: sqlx::types::Json<ContactInfo>,
-    #[serde(rename = "userID")]
-    user_id: i32,
+    #[validate]
+    contact_info: Option<ContactInfo>,
+    /// Reschedules the publication of a draft, `null` keeps it a draft until published by hand.
+    /// Ignored for published markers
+    #[serde(deserialize_with = "nullable::timestamp")]
+    #[serde(rename = "publishAt")]
+    #[serde(default)]
+    publish_at: Option<Option<DateTime<Utc>>>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct FullMarkerOwnedWithDist {
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct FullMarkerOwned {
     id: u32,
     latitude: f64,
     longitude: f64,
     title: String,
     description: String,
-    #[serde(rename = "type")]
-    r#type: EventType,
+    #[serde(rename = "categoryID")]
+    category_id: u32,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    category: MarkerCategory,
     #[serde(with = "ts_seconds")]
     #[serde(rename = "addTime")]
     #[serde(default)]
     add_time: DateTime<Utc>,
+    /// Time of the latest edit, the add time for markers never edited
+    #[serde(with = "ts_seconds")]
+    #[serde(rename = "updateTime")]
+    #[serde(default)]
+    update_time: DateTime<Utc>,
     #[serde(with = "ts_seconds_option")]
     #[serde(rename = "startTime")]
     #[serde(default)]
@@ -108,15 +227,97 @@ pub struct FullMarkerOwnedWithDist {
     #[serde(rename = "endTime")]
     #[serde(default)]
     end_time: Option<DateTime<Utc>>,
+    #[serde(with = "ts_seconds_option")]
+    #[serde(rename = "archivedAt")]
+    #[serde(default)]
+    archived_at: Option<DateTime<Utc>>,
+    /// Set while the marker is hidden by moderation, only its author can see it then
+    #[serde(with = "ts_seconds_option")]
+    #[serde(rename = "hiddenAt")]
+    #[serde(default)]
+    hidden_at: Option<DateTime<Utc>>,
+    /// Set while the marker lies in the trash of its author
+    #[serde(with = "ts_seconds_option")]
+    #[serde(rename = "deletedAt")]
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
+    /// Drafts are listed only for their author
+    #[serde(default)]
+    draft: bool,
+    /// When the draft gets published on its own, if ever
+    #[serde(with = "ts_seconds_option")]
+    #[serde(rename = "publishAt")]
+    #[serde(default)]
+    publish_at: Option<DateTime<Utc>>,
+    recurrence: Option<sqlx::types::Json<Recurrence>>,
+    /// Occurrences of a recurring marker within the requested time window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    #[sqlx(default)]
+    occurrences: Option<sqlx::types::Json<Vec<Occurrence>>>,
+    capacity: Option<u32>,
+    /// Number of people going
+    going: i64,
+    /// Number of people interested
+    interested: i64,
+    /// Number of people on the waitlist
+    waiting: i64,
+    /// Number of comments, replies included
+    comments: i64,
+    upvotes: i64,
+    /// State of a neighbour help request, `None` in categories without help requests
+    #[serde(rename = "helpStatus")]
+    help_status: Option<HelpStatus>,
+    #[serde(rename = "helperID")]
+    helper_id: Option<i32>,
+    images: sqlx::types::Json<Vec<ImageFile>>,
     address: sqlx::types::Json<AddressOwned>,
+    /// Left out of public listings, see `contact::reveal_contact_info`
     #[serde(rename = "contactInfo")]
-    contact_info: sqlx::types::Json<ContactInfo>,
-    #[serde(rename = "distanceInKm")]
-    distance_in_km: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    contact_info: Option<sqlx::types::Json<ContactInfo>>,
     #[serde(rename = "userID")]
     user_id: i32,
+    /// Name of the author
+    #[serde(default)]
+    username: String,
 }
 
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct FullMarkerOwnedWithDist {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    marker: FullMarkerOwned,
+    #[serde(rename = "distanceInKm")]
+    distance_in_km: Option<f64>,
+}
+
+impl AsMut<FullMarkerOwned> for FullMarkerOwned {
+    fn as_mut(&mut self) -> &mut FullMarkerOwned {
+        self
+    }
+}
+
+impl AsMut<FullMarkerOwned> for FullMarkerOwnedWithDist {
+    fn as_mut(&mut self) -> &mut FullMarkerOwned {
+        &mut self.marker
+    }
+}
+
+impl Paginated for FullMarkerOwned {
+    fn cursor(&self) -> Cursor {
+        Cursor::new(self.add_time, self.id)
+    }
+}
+
+impl Paginated for FullMarkerOwnedWithDist {
+    fn cursor(&self) -> Cursor {
+        Cursor::by_distance(self.distance_in_km.unwrap_or_default(), self.marker.id)
+    }
+}
+
+/// Moves the marker to the trash of its author, it can be restored until it is purged
 pub async fn delete_marker(
     db: &sqlx::MySqlPool,
     user_id: u32,
@@ -124,167 +325,621 @@ pub async fn delete_marker(
 ) -> anyhow::Result<FullMarkerOwned> {
     let mut tx = db.begin().await?;
 
-    let marker = sqlx::query_as!(
-        FullMarkerOwned,
-        r#"
-        SELECT id, latitude, longitude, title, description, type as `type: EventType`, add_time, start_time, end_time,
-        address as `address: sqlx::types::Json<AddressOwned>`, contact_info as 'contact_info: sqlx::types::Json<ContactInfo>', user_id
-        FROM markers
-        Where id = ? AND user_id = ?
-        "#,
-        marker_id,user_id
+    let deleted = sqlx::query!(
+        "UPDATE markers SET deleted_at = ? WHERE id = ? AND user_id = ? AND deleted_at IS NULL",
+        Utc::now(),
+        marker_id,
+        user_id
     )
-    .fetch_one(&mut tx)
+    .execute(&mut tx)
     .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(MarkerError::NotFound.into());
+    }
+
+    let marker = find_marker(&mut tx, marker_id, Some(user_id))
+        .await?
+        .ok_or(MarkerError::NotFound)?;
+
+    tx.commit().await?;
+
+    Ok(marker)
+}
+
+/// Moderators may delete any marker, skipping the trash
+pub async fn delete_marker_as_moderator(
+    db: &sqlx::MySqlPool,
+    marker_id: u32,
+) -> anyhow::Result<FullMarkerOwned> {
+    remove_marker(db, marker_id, None).await
+}
+
+async fn remove_marker(
+    db: &sqlx::MySqlPool,
+    marker_id: u32,
+    owner: Option<u32>,
+) -> anyhow::Result<FullMarkerOwned> {
+    let mut tx = db.begin().await?;
+
+    let marker = find_marker(&mut tx, marker_id, owner)
+        .await?
+        .ok_or(MarkerError::NotFound)?;
 
     sqlx::query!(
         r#"
-            DELETE FROM markers WHERE id = ? AND user_id = ?   
+            DELETE FROM markers WHERE id = ?   
             "#,
-        marker_id,
-        user_id
+        marker_id
     )
     .execute(&mut tx)
     .await?;
 
     tx.commit().await?;
+    images::remove_marker_images(marker_id).await;
 
     Ok(marker)
 }
 
-pub async fn show_markers(db: &sqlx::MySqlPool) -> anyhow::Result<Vec<FullMarkerOwned>> {
-    let markers = sqlx::query_as!(
-        FullMarkerOwned,
-        r#"
-        SELECT id, latitude, longitude, title, description, type as `type: EventType`, add_time,start_time, end_time,
-        address as `address: sqlx::types::Json<AddressOwned>`, contact_info as 'contact_info: sqlx::types::Json<ContactInfo>', user_id
+const MARKER_COLUMNS: &str = r#"
+        SELECT id, latitude, longitude, title, description, category_id, add_time, start_time, end_time,
+        archived_at, hidden_at, deleted_at, draft, publish_at, recurrence, capacity, address,
+        contact_info, user_id,
+        (SELECT slug FROM categories WHERE categories.id = markers.category_id) AS category,
+        (SELECT name FROM users WHERE users.id = markers.user_id) AS username,
+        GREATEST(markers.add_time, COALESCE((SELECT MAX(marker_revisions.add_time)
+            FROM marker_revisions WHERE marker_revisions.marker_id = markers.id), markers.add_time))
+            AS update_time,
+        (SELECT COUNT(*) FROM attendance
+            WHERE attendance.marker_id = markers.id AND status = "going") AS going,
+        (SELECT COUNT(*) FROM attendance
+            WHERE attendance.marker_id = markers.id AND status = "interested") AS interested,
+        (SELECT COUNT(*) FROM waitlist WHERE waitlist.marker_id = markers.id) AS waiting,
+        (SELECT COUNT(*) FROM comments WHERE comments.marker_id = markers.id) AS comments,
+        (SELECT COUNT(*) FROM marker_votes WHERE marker_votes.marker_id = markers.id) AS upvotes,
+        IF((SELECT help FROM categories WHERE categories.id = markers.category_id), COALESCE(
+            (SELECT status FROM help_requests WHERE help_requests.marker_id = markers.id), "open"
+        ), NULL) AS help_status,
+        (SELECT helper_id FROM help_requests WHERE help_requests.marker_id = markers.id) AS helper_id,
+        (SELECT COALESCE(JSON_ARRAYAGG(JSON_OBJECT("id", id, "marker_id", marker_id, "name", file_name)), JSON_ARRAY())
+            FROM marker_images WHERE marker_images.marker_id = markers.id) AS images
         FROM markers
-        "#
-    )
-    .fetch_all(db)
-    .await?;
+        WHERE TRUE"#;
 
-    Ok(markers)
+/// Looks a marker up regardless of its state, optionally only among the markers of one user
+async fn find_marker<'e>(
+    executor: impl sqlx::MySqlExecutor<'e>,
+    id: u32,
+    owner: Option<u32>,
+) -> anyhow::Result<Option<FullMarkerOwned>> {
+    let mut query = QueryBuilder::new(MARKER_COLUMNS);
+    query.push(" AND id = ").push_bind(id);
+    if let Some(owner) = owner {
+        query.push(" AND user_id = ").push_bind(owner);
+    }
+
+    let marker = query.build_query_as().fetch_optional(executor).await?;
+
+    Ok(marker)
 }
 
-pub async fn show_markers_by_city(
+pub async fn show_markers(
     db: &sqlx::MySqlPool,
-    city: &str,
-) -> anyhow::Result<Vec<FullMarkerOwned>> {
-    let markers = sqlx::query_as!(
-        FullMarkerOwned,
-        r#"
-        SELECT id, latitude, longitude, title, description, type as `type: EventType`, add_time,start_time, end_time,
-        address as `address: sqlx::types::Json<AddressOwned>`, contact_info as 'contact_info: sqlx::types::Json<ContactInfo>', user_id
-        FROM markers WHERE JSON_EXTRACT(address,"$.city") = ?
-        "#,
-        city
-    )
-    .fetch_all(db)
-    .await?;
+    filter: MarkerFilter<'_>,
+    page: PageRequest,
+) -> anyhow::Result<Page<FullMarkerOwned>> {
+    let mut query = QueryBuilder::new(MARKER_COLUMNS);
+    filter.push_conditions(&mut query);
+    page.push_condition(&mut query);
+    page.push_order_and_limit(&mut query);
 
-    Ok(markers)
+    let markers = query.build_query_as().fetch_all(db).await?;
+    let mut page = page.into_page(markers);
+    filter.expand_occurrences(page.items_mut());
+    contact::hide_contact_info(page.items_mut());
+
+    Ok(page)
 }
 
+pub async fn show_markers_by_city<'r>(
+    db: &sqlx::MySqlPool,
+    city: &'r str,
+    filter: MarkerFilter<'r>,
+    page: PageRequest,
+) -> anyhow::Result<Page<FullMarkerOwned>> {
+    show_markers(db, filter.in_city(city), page).await
+}
+
+/// Markers within `dist` kilometres, nearest first. Takes a `PageRequest::nearest`
 pub async fn show_markers_by_dist(
     db: &sqlx::MySqlPool,
     x: f64,
     y: f64,
     dist: u32,
-) -> anyhow::Result<Vec<FullMarkerOwnedWithDist>> {
-    // SELECT id, latitude, longitude, title, type as `event_type: EventType`,user_id
+    filter: MarkerFilter<'_>,
+    page: PageRequest,
+) -> anyhow::Result<Page<FullMarkerOwnedWithDist>> {
     // Thanks for the formula: http://www.plumislandmedia.net/mysql/haversine-mysql-nearest-loc/
-    let markers = sqlx::query_as!(
-        FullMarkerOwnedWithDist,
+    let mut query = QueryBuilder::new(
         r#"
-        SELECT z.id, z.latitude, z.longitude, z.title, z.description, z.type as `type: EventType`, z.add_time,start_time, z.end_time,
-        z.address as `address: sqlx::types::Json<AddressOwned>`, z.contact_info as 'contact_info: sqlx::types::Json<ContactInfo>', z.user_id,
-        p.distance_unit
-                * DEGREES(ACOS(LEAST(1.0, COS(RADIANS(p.latpoint))
-                * COS(RADIANS(z.latitude))
-                * COS(RADIANS(p.longpoint) - RADIANS(z.longitude))
-                + SIN(RADIANS(p.latpoint))
-                * SIN(RADIANS(z.latitude))))) AS distance_in_km
-        FROM markers AS z
-        JOIN (   /* these are the query parameters */
-            SELECT  ?  AS latpoint,      ? AS longpoint,
-                    ? AS radius,      111.045 AS distance_unit
-        ) AS p ON 1=1
-        WHERE z.latitude
-        BETWEEN p.latpoint  - (p.radius / p.distance_unit)
-            AND p.latpoint  + (p.radius / p.distance_unit)
-        AND z.longitude
-        BETWEEN p.longpoint - (p.radius / (p.distance_unit * COS(RADIANS(p.latpoint))))
-            AND p.longpoint + (p.radius / (p.distance_unit * COS(RADIANS(p.latpoint))))
-        ORDER BY distance_in_km
-        LIMIT 15;
-        "#,
-        x,
-        y,
-        dist
-    )
-    .fetch_all(db)
-    .await?;
+        SELECT * FROM (
+            SELECT z.*, p.radius,
+            (SELECT slug FROM categories WHERE categories.id = z.category_id) AS category,
+            (SELECT name FROM users WHERE users.id = z.user_id) AS username,
+            GREATEST(z.add_time, COALESCE((SELECT MAX(marker_revisions.add_time)
+                FROM marker_revisions WHERE marker_revisions.marker_id = z.id), z.add_time))
+                AS update_time,
+            (SELECT COUNT(*) FROM attendance
+                WHERE attendance.marker_id = z.id AND status = "going") AS going,
+            (SELECT COUNT(*) FROM attendance
+                WHERE attendance.marker_id = z.id AND status = "interested") AS interested,
+            (SELECT COUNT(*) FROM waitlist WHERE waitlist.marker_id = z.id) AS waiting,
+            (SELECT COUNT(*) FROM comments WHERE comments.marker_id = z.id) AS comments,
+            (SELECT COUNT(*) FROM marker_votes WHERE marker_votes.marker_id = z.id) AS upvotes,
+            IF((SELECT help FROM categories WHERE categories.id = z.category_id), COALESCE(
+                (SELECT status FROM help_requests WHERE help_requests.marker_id = z.id), "open"
+            ), NULL) AS help_status,
+            (SELECT helper_id FROM help_requests WHERE help_requests.marker_id = z.id) AS helper_id,
+            (SELECT COALESCE(JSON_ARRAYAGG(JSON_OBJECT("id", id, "marker_id", marker_id, "name", file_name)), JSON_ARRAY())
+                FROM marker_images WHERE marker_images.marker_id = z.id) AS images,
+            p.distance_unit
+                    * DEGREES(ACOS(LEAST(1.0, COS(RADIANS(p.latpoint))
+                    * COS(RADIANS(z.latitude))
+                    * COS(RADIANS(p.longpoint) - RADIANS(z.longitude))
+                    + SIN(RADIANS(p.latpoint))
+                    * SIN(RADIANS(z.latitude))))) AS distance_in_km
+            FROM markers AS z
+            JOIN (   /* these are the query parameters */
+                SELECT "#,
+    );
+    query
+        .push_bind(x)
+        .push(" AS latpoint, ")
+        .push_bind(y)
+        .push(" AS longpoint, ")
+        .push_bind(dist)
+        .push(
+            r#" AS radius, 111.045 AS distance_unit
+            ) AS p ON 1=1
+            WHERE z.latitude
+            BETWEEN p.latpoint  - (p.radius / p.distance_unit)
+                AND p.latpoint  + (p.radius / p.distance_unit)
+            AND z.longitude
+            BETWEEN p.longpoint - (p.radius / (p.distance_unit * COS(RADIANS(p.latpoint))))
+                AND p.longpoint + (p.radius / (p.distance_unit * COS(RADIANS(p.latpoint))))
+        ) AS d
+        WHERE distance_in_km <= radius"#,
+        );
+    filter.push_conditions(&mut query);
+    page.push_condition(&mut query);
+    page.push_order_and_limit(&mut query);
+
+    let markers = query.build_query_as().fetch_all(db).await?;
+    let mut page = page.into_page(markers);
+    filter.expand_occurrences(page.items_mut());
+    contact::hide_contact_info(page.items_mut());
+
+    Ok(page)
+}
+
+/// Markers visible in the viewport, newest first. Paged like the other listings, a crowded
+/// viewport would otherwise come back whole
+pub async fn show_markers_in_bbox(
+    db: &sqlx::MySqlPool,
+    bbox: &BoundingBox,
+    filter: MarkerFilter<'_>,
+    page: PageRequest,
+) -> anyhow::Result<Page<FullMarkerOwned>> {
+    let mut query = QueryBuilder::new(MARKER_COLUMNS);
+    bbox.push_condition(&mut query);
+    filter.push_conditions(&mut query);
+    page.push_condition(&mut query);
+    page.push_order_and_limit(&mut query);
+
+    let markers = query.build_query_as().fetch_all(db).await?;
+    let mut page = page.into_page(markers);
+    filter.expand_occurrences(page.items_mut());
+    contact::hide_contact_info(page.items_mut());
+
+    Ok(page)
+}
+
+/// Markers with a start time, latest events first, for calendar feeds
+pub async fn show_scheduled_markers(
+    db: &sqlx::MySqlPool,
+    filter: MarkerFilter<'_>,
+) -> anyhow::Result<Vec<FullMarkerOwned>> {
+    let mut query = QueryBuilder::new(MARKER_COLUMNS);
+    query.push(" AND start_time IS NOT NULL");
+    filter.push_conditions(&mut query);
+    query
+        .push(" ORDER BY start_time DESC LIMIT ")
+        .push_bind(pagination::MAX_PAGE_SIZE);
+
+    let mut markers: Vec<FullMarkerOwned> = query.build_query_as().fetch_all(db).await?;
+    filter.expand_occurrences(&mut markers);
+    contact::hide_contact_info(&mut markers);
 
     Ok(markers)
 }
 
-pub async fn show_user_markers(
+/// Markers matching the phrase in their title or description, best matches first
+pub async fn search_markers(
     db: &sqlx::MySqlPool,
-    user_id: u32,
+    phrase: &str,
+    filter: MarkerFilter<'_>,
+    limit: Option<u32>,
 ) -> anyhow::Result<Vec<FullMarkerOwned>> {
-    let markers = sqlx::query_as!(
-        FullMarkerOwned,
-        r#"
-        SELECT id, latitude, longitude, title, description, type as `type: EventType`, add_time,start_time, end_time,
-        address as `address: sqlx::types::Json<AddressOwned>`, contact_info as 'contact_info: sqlx::types::Json<ContactInfo>', user_id
-        FROM markers WHERE user_id = ?
-        "#,
-        user_id
-    )
-    .fetch_all(db)
-    .await?;
+    let mut query = QueryBuilder::new(MARKER_COLUMNS);
+    query
+        .push(" AND MATCH(title, description) AGAINST (")
+        .push_bind(phrase)
+        .push(" IN NATURAL LANGUAGE MODE)");
+    filter.push_conditions(&mut query);
+    query
+        .push(" ORDER BY MATCH(title, description) AGAINST (")
+        .push_bind(phrase)
+        .push(" IN NATURAL LANGUAGE MODE) DESC, add_time DESC LIMIT ")
+        .push_bind(
+            limit
+                .unwrap_or(pagination::DEFAULT_PAGE_SIZE)
+                .clamp(1, pagination::MAX_PAGE_SIZE),
+        );
+
+    let mut markers: Vec<FullMarkerOwned> = query.build_query_as().fetch_all(db).await?;
+    filter.expand_occurrences(&mut markers);
+    contact::hide_contact_info(&mut markers);
 
     Ok(markers)
 }
 
-/* pub async fn show_marker(db: &sqlx::MySqlPool, id: u32) -> anyhow::Result<FullMarkerOwned> {
-    let marker = sqlx::query_as!(
-        FullMarkerOwned,
-        r#"
-        SELECT id, latitude, longitude, title, description, type as `type: EventType`, add_time,start_time, end_time,
-        address as `address: sqlx::types::Json<AddressOwned>`, contact_info as 'contact_info: sqlx::types::Json<ContactInfo>', user_id
-        FROM markers Where id = ?
-        "#,
-        id
-    )
-    .fetch_one(db)
-    .await?;
+pub async fn show_user_markers(
+    db: &sqlx::MySqlPool,
+    user_id: u32,
+    filter: MarkerFilter<'_>,
+    page: PageRequest,
+) -> anyhow::Result<Page<FullMarkerOwned>> {
+    let filter = filter.by_author(user_id);
+    let mut query = QueryBuilder::new(MARKER_COLUMNS);
+    filter.push_conditions_with_hidden(&mut query);
+    page.push_condition(&mut query);
+    page.push_order_and_limit(&mut query);
+
+    let markers = query.build_query_as().fetch_all(db).await?;
+    let mut page = page.into_page(markers);
+    filter.expand_occurrences(page.items_mut());
+
+    Ok(page)
+}
+
+/// Hidden, deleted and draft markers can't be looked up, even by their id
+pub async fn show_marker(db: &sqlx::MySqlPool, id: u32) -> anyhow::Result<Option<FullMarkerOwned>> {
+    let mut marker = find_marker(db, id, None).await?.filter(|marker| {
+        marker.hidden_at.is_none() && marker.deleted_at.is_none() && !marker.draft
+    });
+    if let Some(marker) = &mut marker {
+        marker.contact_info = None;
+    }
 
     Ok(marker)
-} */
+}
+
+/// Phone numbers may start with `+` and group their 9 to 15 digits with spaces or dashes
+fn validate_contact_method(method: &ContactMethod) -> Result<(), ValidationError> {
+    match method {
+        ContactMethod::Email(email) if validator::validate_email(email) => Ok(()),
+        ContactMethod::Email(_) => Err(ValidationError::new("email")),
+        ContactMethod::PhoneNumber(number) => {
+            let number = number.strip_prefix('+').unwrap_or(number);
+            let digits = number.chars().filter(char::is_ascii_digit).count();
+            let well_formed = number
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == ' ' || c == '-');
+
+            if well_formed && (9..=15).contains(&digits) {
+                Ok(())
+            } else {
+                Err(ValidationError::new("phone"))
+            }
+        }
+    }
+}
+
+/// Reported under the name of the field at fault, so clients can treat it like the others
+fn validate_times(marker: &FullMarker<'_>) -> Result<(), ValidationError> {
+    match check_times(
+        marker.start_time,
+        marker.end_time,
+        marker.recurrence.as_ref(),
+    ) {
+        Ok(_) => Ok(()),
+        Err(MarkerError::RecurrenceWithoutStart) => Err(ValidationError::new("startTime")),
+        Err(_) => Err(ValidationError::new("endTime")),
+    }
+}
+
+/// Checks that the times of a marker make sense and returns the end of its series
+/// if it is a recurring one
+fn check_times(
+    start_time: Option<DateTime<Utc>>,
+    end_time: Option<DateTime<Utc>>,
+    recurrence: Option<&Recurrence>,
+) -> Result<Option<DateTime<Utc>>, MarkerError> {
+    if let (Some(start), Some(end)) = (start_time, end_time) {
+        if end < start {
+            return Err(MarkerError::EndBeforeStart);
+        }
+    }
+
+    match (recurrence, start_time) {
+        (None, _) => Ok(None),
+        (Some(_), None) => Err(MarkerError::RecurrenceWithoutStart),
+        (Some(recurrence), Some(start_time)) => Ok(recurrence.series_end(start_time, end_time)),
+    }
+}
+
+impl<'r> MarkerUpdate<'r> {
+    pub async fn update_marker(
+        &self,
+        db: &sqlx::MySqlPool,
+        mailer: &Mailer,
+        user_id: u32,
+        marker_id: u32,
+    ) -> anyhow::Result<FullMarkerOwned> {
+        let mut tx = db.begin().await?;
+
+        let current = sqlx::query!(
+            r#"
+            SELECT start_time, end_time, recurrence as `recurrence: sqlx::types::Json<Recurrence>`
+            FROM markers
+            WHERE id = ? AND user_id = ? AND deleted_at IS NULL
+            FOR UPDATE
+            "#,
+            marker_id,
+            user_id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(MarkerError::NotFound)?;
+
+        let start_time = self.start_time.unwrap_or(current.start_time);
+        let end_time = self.end_time.unwrap_or(current.end_time);
+        let recurrence = match &self.recurrence {
+            Some(recurrence) => recurrence.as_ref(),
+            None => current.recurrence.as_ref().map(|recurrence| &recurrence.0),
+        };
+        let series_end = check_times(start_time, end_time, recurrence)?;
+        let category_id =
+            categories::resolve_category(&mut tx, self.category_id, self.r#type).await?;
+
+        let before = revisions::snapshot(&mut tx, marker_id).await?;
+
+        let address = match &self.address {
+            Some(address) => Some(serde_json::to_string(address)?),
+            None => None,
+        };
+        let contact_info = match &self.contact_info {
+            Some(contact_info) => Some(serde_json::to_string(contact_info)?),
+            None => None,
+        };
+        let recurrence = match &self.recurrence {
+            Some(Some(recurrence)) => Some(serde_json::to_string(recurrence)?),
+            _ => None,
+        };
+
+        // Changing the time of an event lets the archiver judge it again
+        sqlx::query!(
+            r#"
+            UPDATE markers SET
+            latitude = COALESCE(?, latitude), longitude = COALESCE(?, longitude),
+            title = COALESCE(?, title), description = COALESCE(?, description),
+            category_id = COALESCE(?, category_id),
+            start_time = IF(?, ?, start_time), end_time = IF(?, ?, end_time),
+            address = COALESCE(?, address), contact_info = COALESCE(?, contact_info),
+            recurrence = IF(?, ?, recurrence), series_end = ?, capacity = IF(?, ?, capacity),
+            publish_at = IF(draft AND ?, ?, publish_at),
+            archived_at = IF(? OR ? OR ?, NULL, archived_at)
+            WHERE id = ? AND user_id = ?
+            "#,
+            self.latitude,
+            self.longitude,
+            self.title,
+            self.description,
+            category_id,
+            self.start_time.is_some(),
+            self.start_time.flatten(),
+            self.end_time.is_some(),
+            self.end_time.flatten(),
+            address,
+            contact_info,
+            self.recurrence.is_some(),
+            recurrence,
+            series_end,
+            self.capacity.is_some(),
+            self.capacity.flatten(),
+            self.publish_at.is_some(),
+            self.publish_at.flatten(),
+            self.start_time.is_some(),
+            self.end_time.is_some(),
+            self.recurrence.is_some(),
+            marker_id,
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+        revisions::record(&mut tx, marker_id, user_id, before).await?;
+        let promoted = match self.capacity {
+            Some(_) => attendance::fill_capacity(&mut tx, marker_id).await?,
+            None => Vec::new(),
+        };
+
+        let marker = find_marker(&mut tx, marker_id, Some(user_id))
+            .await?
+            .ok_or(MarkerError::NotFound)?;
+
+        tx.commit().await?;
+        attendance::notify_promoted(mailer, &marker.title, promoted).await;
+
+        Ok(marker)
+    }
+}
+
 impl<'r> FullMarker<'r> {
     pub async fn add_marker(&self, db: &sqlx::MySqlPool, user_id: u32) -> anyhow::Result<bool> {
+        let series_end = check_times(self.start_time, self.end_time, self.recurrence.as_ref())?;
+        let mut tx = db.begin().await?;
+        let category_id = categories::resolve_category(&mut tx, self.category_id, self.r#type)
+            .await?
+            .ok_or(MarkerError::UnknownCategory)?;
+        let recurrence = match &self.recurrence {
+            Some(recurrence) => Some(serde_json::to_string(recurrence)?),
+            None => None,
+        };
+        // Publication times already gone by are dropped, such markers go public right away
+        // unless they are explicit drafts
+        let now = Utc::now();
+        let publish_at = self.publish_at.filter(|publish_at| *publish_at > now);
+        let draft = self.draft || publish_at.is_some();
+
         let added = sqlx::query!(
             r#"
             INSERT INTO `markers` (`latitude`, `longitude`, `title`, `description`,
-            `type`, `add_time`, `start_time`, `end_time`, `address`, `contact_info`, `user_id`) 
-            VALUES (?,?,?,?,?,?,?,?,?,?,?)"#,
+            `category_id`, `add_time`, `start_time`, `end_time`, `recurrence`, `series_end`, `capacity`, `address`, `contact_info`, `draft`, `publish_at`, `user_id`) 
+            VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)"#,
             self.latitude,
             self.longitude,
             self.title,
             self.description,
-            self.r#type,
-            chrono::offset::Utc::now(),
+            category_id,
+            now,
             self.start_time,
             self.end_time,
+            recurrence,
+            series_end,
+            self.capacity,
             serde_json::to_string(&self.address)?,
             serde_json::to_string(&self.contact_info)?,
+            draft,
+            publish_at,
             user_id
         )
-        .execute(db)
+        .execute(&mut tx)
+        .await?;
+        if added.rows_affected() == 0 {
+            return Ok(false);
+        }
+        revisions::record_creation(&mut tx, added.last_insert_id() as u32, user_id).await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use chrono::{Duration, TimeZone};
+    use sqlx::MySqlPool;
+
+    fn phone(number: &str) -> ContactMethod {
+        ContactMethod::PhoneNumber(number.to_string())
+    }
+
+    #[test]
+    fn accepts_well_formed_contact_methods() {
+        let email = ContactMethod::Email("jan@example.com".to_string());
+        assert!(validate_contact_method(&email).is_ok());
+        for number in [
+            "123456789",
+            "+48 123 456 789",
+            "123-456-789",
+            "123456789012345",
+        ] {
+            assert!(
+                validate_contact_method(&phone(number)).is_ok(),
+                "{}",
+                number
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_contact_methods() {
+        let email = ContactMethod::Email("jan".to_string());
+        assert!(validate_contact_method(&email).is_err());
+        for number in [
+            "12345678",
+            "1234567890123456",
+            "123 456 78x9",
+            "++48123456789",
+        ] {
+            assert!(
+                validate_contact_method(&phone(number)).is_err(),
+                "{}",
+                number
+            );
+        }
+    }
+
+    #[test]
+    fn checks_times() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let end = start + Duration::hours(2);
+        let recurrence: Recurrence = serde_json::from_str(r#"{"freq":"DAILY","count":3}"#).unwrap();
+
+        assert!(matches!(
+            check_times(Some(start), Some(end), None),
+            Ok(None)
+        ));
+        assert!(matches!(check_times(None, Some(end), None), Ok(None)));
+        assert!(matches!(
+            check_times(Some(end), Some(start), None),
+            Err(MarkerError::EndBeforeStart)
+        ));
+        assert!(matches!(
+            check_times(None, None, Some(&recurrence)),
+            Err(MarkerError::RecurrenceWithoutStart)
+        ));
+        assert!(matches!(
+            check_times(Some(start), Some(end), Some(&recurrence)),
+            Ok(Some(series_end)) if series_end == end + Duration::days(2)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn pages_markers_in_the_viewport(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let author = testing::add_user(&db, "jan").await?;
+        let first = testing::add_marker(&db, author, "Pierwszy").await?;
+        let second = testing::add_marker(&db, author, "Drugi").await?;
+        let third = testing::add_marker(&db, author, "Trzeci").await?;
+        let bbox = BoundingBox::new(50.0, 19.0, 50.5, 19.5).unwrap();
+
+        let page = PageRequest::new(None, Some(2)).unwrap();
+        let page = show_markers_in_bbox(&db, &bbox, MarkerFilter::default(), page).await?;
+        let (markers, next) = page.into_parts();
+        let ids: Vec<u32> = markers.iter().map(|marker| marker.id).collect();
+        assert_eq!(ids, [third, second]);
+        let next = next.expect("a next page");
+
+        let page = PageRequest::new(Some(&next), Some(2)).unwrap();
+        let page = show_markers_in_bbox(&db, &bbox, MarkerFilter::default(), page).await?;
+        let (markers, next) = page.into_parts();
+        let ids: Vec<u32> = markers.iter().map(|marker| marker.id).collect();
+        assert_eq!(ids, [first]);
+        assert!(next.is_none());
+
+        let elsewhere = BoundingBox::new(52.0, 20.0, 52.5, 21.0).unwrap();
+        let page = show_markers_in_bbox(
+            &db,
+            &elsewhere,
+            MarkerFilter::default(),
+            PageRequest::first(),
+        )
         .await?;
+        assert!(page.into_parts().0.is_empty());
 
-        Ok(added.rows_affected() > 0)
+        Ok(())
     }
 }
@@ -0,0 +1,21 @@
+use chrono::serde::ts_seconds_option;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer};
+
+/// Deserializes a field of a partial update that can be cleared. Use it together with
+/// `#[serde(default)]`: a missing field stays `None`, `null` becomes `Some(None)`
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Same as `deserialize`, for timestamps given in seconds
+pub fn timestamp<'de, D>(deserializer: D) -> Result<Option<Option<DateTime<Utc>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    ts_seconds_option::deserialize(deserializer).map(Some)
+}
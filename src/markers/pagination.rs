@@ -0,0 +1,260 @@
+use chrono::{DateTime, TimeZone, Utc};
+use rocket::http::Header;
+use rocket::response::{self, Responder};
+use rocket::Request;
+use serde::Serialize;
+use sqlx::{MySql, QueryBuilder};
+
+use crate::SomsiadStatus;
+
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+pub const MAX_PAGE_SIZE: u32 = 200;
+/// Carries the cursor of the next page, the body of a page is the same array as before
+pub const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
+
+/// What a listing is ordered by, ties are broken by id
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Key {
+    /// Newest first
+    AddTime(DateTime<Utc>),
+    /// Nearest first
+    Distance(f64),
+}
+
+/// Position of the last item on a page, the next page starts right after it
+#[derive(PartialEq, Debug)]
+pub struct Cursor {
+    key: Key,
+    id: u32,
+}
+
+impl Cursor {
+    pub fn new(add_time: DateTime<Utc>, id: u32) -> Self {
+        Self {
+            key: Key::AddTime(add_time),
+            id,
+        }
+    }
+
+    pub fn by_distance(distance_in_km: f64, id: u32) -> Self {
+        Self {
+            key: Key::Distance(distance_in_km),
+            id,
+        }
+    }
+
+    /// Clients should treat the cursor as an opaque token, hence the hex encoding
+    pub fn encode(&self) -> String {
+        let text = match self.key {
+            Key::AddTime(add_time) => format!("{}.{}", add_time.timestamp(), self.id),
+            Key::Distance(distance) => format!("d{}.{}", distance, self.id),
+        };
+        text.bytes().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn decode(raw: &str) -> Option<Self> {
+        if raw.len() % 2 != 0 {
+            return None;
+        }
+        let bytes = (0..raw.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(raw.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let text = String::from_utf8(bytes).ok()?;
+        let (key, id) = text.rsplit_once('.')?;
+        let key = match key.strip_prefix('d') {
+            Some(distance) => Key::Distance(distance.parse().ok().filter(|d: &f64| d.is_finite())?),
+            None => Key::AddTime(Utc.timestamp_opt(key.parse().ok()?, 0).single()?),
+        };
+
+        Some(Self {
+            key,
+            id: id.parse().ok()?,
+        })
+    }
+
+    fn is_by_distance(&self) -> bool {
+        matches!(self.key, Key::Distance(_))
+    }
+}
+
+pub trait Paginated {
+    fn cursor(&self) -> Cursor;
+}
+
+pub struct PageRequest {
+    cursor: Option<Cursor>,
+    limit: u32,
+    by_distance: bool,
+}
+
+impl PageRequest {
+    /// Returns `None` when the cursor can't be decoded, the limit is clamped to `MAX_PAGE_SIZE`
+    pub fn new(cursor: Option<&str>, limit: Option<u32>) -> Option<Self> {
+        Self::with_order(cursor, limit, false)
+    }
+
+    /// Same as `new`, for listings ordered nearest first
+    pub fn nearest(cursor: Option<&str>, limit: Option<u32>) -> Option<Self> {
+        Self::with_order(cursor, limit, true)
+    }
+
+    fn with_order(cursor: Option<&str>, limit: Option<u32>, by_distance: bool) -> Option<Self> {
+        // A cursor of a listing ordered differently can't point into this one
+        let cursor = match cursor {
+            Some(raw) => Some(Cursor::decode(raw).filter(|c| c.is_by_distance() == by_distance)?),
+            None => None,
+        };
+
+        Some(Self {
+            cursor,
+            limit: limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+            by_distance,
+        })
+    }
+
+    /// Newest markers, used where the client can't follow cursors
+    pub fn first() -> Self {
+        Self {
+            cursor: None,
+            limit: DEFAULT_PAGE_SIZE,
+            by_distance: false,
+        }
+    }
+
+    /// Appends the keyset condition to an already started `WHERE` clause
+    pub(crate) fn push_condition(&self, builder: &mut QueryBuilder<'_, MySql>) {
+        let cursor = match &self.cursor {
+            Some(cursor) => cursor,
+            None => return,
+        };
+        match cursor.key {
+            Key::AddTime(add_time) => builder
+                .push(" AND (add_time < ")
+                .push_bind(add_time)
+                .push(" OR (add_time = ")
+                .push_bind(add_time)
+                .push(" AND id < ")
+                .push_bind(cursor.id)
+                .push("))"),
+            Key::Distance(distance) => builder
+                .push(" AND (distance_in_km > ")
+                .push_bind(distance)
+                .push(" OR (distance_in_km = ")
+                .push_bind(distance)
+                .push(" AND id > ")
+                .push_bind(cursor.id)
+                .push("))"),
+        };
+    }
+
+    /// One row more than requested is fetched to find out whether there is a next page
+    pub(crate) fn push_order_and_limit(&self, builder: &mut QueryBuilder<'_, MySql>) {
+        if self.by_distance {
+            builder.push(" ORDER BY distance_in_km, id LIMIT ");
+        } else {
+            builder.push(" ORDER BY add_time DESC, id DESC LIMIT ");
+        }
+        builder.push_bind(self.limit + 1);
+    }
+
+    pub(crate) fn into_page<T: Paginated>(self, mut items: Vec<T>) -> Page<T> {
+        let limit = self.limit as usize;
+        let next = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|item| item.cursor().encode())
+        } else {
+            None
+        };
+
+        Page { items, next }
+    }
+}
+
+pub struct Page<T> {
+    items: Vec<T>,
+    next: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub(crate) fn items_mut(&mut self) -> &mut Vec<T> {
+        &mut self.items
+    }
+
+    pub fn into_parts(self) -> (Vec<T>, Option<String>) {
+        (self.items, self.next)
+    }
+}
+
+/// Responds with the items like any other listing, the cursor of the next page is sent in
+/// the `X-Next-Cursor` header and left out on the last page
+impl<'r, T: Serialize> Responder<'r, 'static> for Page<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = SomsiadStatus::ok(self.items).respond_to(request)?;
+        if let Some(next) = self.next {
+            response.set_header(Header::new(NEXT_CURSOR_HEADER, next));
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> String {
+        text.bytes().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let add_time = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let cursor = Cursor::new(add_time, 42);
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn distance_cursor_round_trips() {
+        let cursor = Cursor::by_distance(1.25, 7);
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        let malformed = [
+            String::from("abc"),
+            String::from("zz"),
+            hex("1700000000"),
+            hex("1700000000.x"),
+            hex("dNaN.1"),
+        ];
+        for raw in &malformed {
+            assert_eq!(Cursor::decode(raw), None, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn cursors_only_fit_listings_with_the_same_order() {
+        let by_distance = Cursor::by_distance(1.0, 1).encode();
+        let by_time = Cursor::new(Utc.timestamp_opt(0, 0).unwrap(), 1).encode();
+
+        assert!(PageRequest::new(Some(&by_distance), None).is_none());
+        assert!(PageRequest::nearest(Some(&by_distance), None).is_some());
+        assert!(PageRequest::new(Some(&by_time), None).is_some());
+        assert!(PageRequest::nearest(Some(&by_time), None).is_none());
+    }
+
+    #[test]
+    fn limit_is_clamped() {
+        assert_eq!(
+            PageRequest::new(None, None).unwrap().limit,
+            DEFAULT_PAGE_SIZE
+        );
+        assert_eq!(PageRequest::new(None, Some(0)).unwrap().limit, 1);
+        assert_eq!(
+            PageRequest::new(None, Some(10_000)).unwrap().limit,
+            MAX_PAGE_SIZE
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use chrono::serde::{ts_seconds, ts_seconds_option};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use super::FullMarkerOwned;
+
+/// Upper bound of steps taken through a series, endless ones included
+const MAX_STEPS: u32 = 10_000;
+/// Upper bound of occurrences returned for one marker
+const MAX_EXPANDED: usize = 366;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// Subset of the RFC 5545 RRULE. Occurrences are computed in UTC, starting with the
+/// `startTime` of the marker
+#[derive(Serialize, Deserialize, Validate, Clone)]
+#[validate(schema(function = "validate_recurrence"))]
+pub struct Recurrence {
+    freq: Frequency,
+    #[serde(default = "default_interval")]
+    #[validate(range(min = 1, max = 366))]
+    interval: u32,
+    #[serde(with = "ts_seconds_option")]
+    #[serde(default)]
+    until: Option<DateTime<Utc>>,
+    #[validate(range(min = 1, max = 1000))]
+    count: Option<u32>,
+    /// Unix timestamps of the starts of cancelled occurrences
+    #[serde(default)]
+    exceptions: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Occurrence {
+    #[serde(with = "ts_seconds")]
+    #[serde(rename = "startTime")]
+    start_time: DateTime<Utc>,
+    #[serde(with = "ts_seconds_option")]
+    #[serde(rename = "endTime")]
+    #[serde(default)]
+    end_time: Option<DateTime<Utc>>,
+}
+
+fn default_interval() -> u32 {
+    1
+}
+
+/// UNTIL and COUNT must not occur together in one rule
+fn validate_recurrence(recurrence: &Recurrence) -> Result<(), ValidationError> {
+    if recurrence.until.is_some() && recurrence.count.is_some() {
+        return Err(ValidationError::new("until_and_count"));
+    }
+    Ok(())
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+impl Recurrence {
+    /// Start of the `n`-th step of the series. Months lacking the day of the first occurrence
+    /// are skipped, just like RFC 5545 requires
+    fn nth_start(&self, first: DateTime<Utc>, n: u32) -> Option<DateTime<Utc>> {
+        let step = n.checked_mul(self.interval)?;
+        match self.freq {
+            Frequency::Daily => first.checked_add_signed(Duration::days(step.into())),
+            Frequency::Weekly => first.checked_add_signed(Duration::weeks(step.into())),
+            Frequency::Monthly => {
+                let months = first.month0().checked_add(step)?;
+                let year = first.year().checked_add((months / 12).try_into().ok()?)?;
+                let date = NaiveDate::from_ymd_opt(year, months % 12 + 1, first.day())?;
+                Some(DateTime::from_utc(date.and_time(first.time()), Utc))
+            }
+        }
+    }
+
+    /// Starts of all occurrences in chronological order, cancelled ones left out
+    pub fn starts(&self, first: DateTime<Utc>) -> impl Iterator<Item = DateTime<Utc>> + '_ {
+        (0..MAX_STEPS)
+            .filter_map(move |n| self.nth_start(first, n))
+            .take_while(move |start| self.until.map_or(true, |until| *start <= until))
+            .take(self.count.map_or(usize::MAX, |count| count as usize))
+            .filter(move |start| !self.exceptions.contains(&start.timestamp()))
+    }
+
+    /// End of the last occurrence, `None` for endless series
+    pub fn series_end(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        if self.until.is_none() && self.count.is_none() {
+            return None;
+        }
+        let last_start = self.starts(start_time).last().unwrap_or(start_time);
+
+        Some(end_time.map_or(last_start, |end_time| last_start + (end_time - start_time)))
+    }
+
+    /// Occurrences overlapping the window, every one as long as the first
+    pub fn occurrences_between(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: Option<DateTime<Utc>>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Vec<Occurrence> {
+        let duration = end_time.map(|end_time| end_time - start_time);
+        self.starts(start_time)
+            .map(|start| Occurrence {
+                start_time: start,
+                end_time: duration.map(|duration| start + duration),
+            })
+            .skip_while(|occurrence| {
+                from.map_or(false, |from| {
+                    occurrence.end_time.unwrap_or(occurrence.start_time) < from
+                })
+            })
+            .take_while(|occurrence| to.map_or(true, |to| occurrence.start_time <= to))
+            .take(MAX_EXPANDED)
+            .collect()
+    }
+
+    /// The rule as an RRULE value, without the property name
+    pub fn to_rrule(&self) -> String {
+        let freq = match self.freq {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+        };
+        let mut rule = format!("FREQ={};INTERVAL={}", freq, self.interval);
+        if let Some(until) = &self.until {
+            rule.push_str(&format!(";UNTIL={}", format_time(until)));
+        }
+        if let Some(count) = self.count {
+            rule.push_str(&format!(";COUNT={}", count));
+        }
+        rule
+    }
+
+    /// Cancelled occurrences as an EXDATE value, `None` when there are none
+    pub fn to_exdate(&self) -> Option<String> {
+        let dates: Vec<String> = self
+            .exceptions
+            .iter()
+            .filter_map(|timestamp| Utc.timestamp_opt(*timestamp, 0).single())
+            .map(|date| format_time(&date))
+            .collect();
+
+        (!dates.is_empty()).then(|| dates.join(","))
+    }
+}
+
+/// Fills in the occurrences of recurring markers falling into the window and drops the series
+/// that have none there
+pub(crate) fn expand<T: AsMut<FullMarkerOwned>>(
+    markers: &mut Vec<T>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) {
+    markers.retain_mut(|marker| {
+        let marker = marker.as_mut();
+        let (recurrence, start_time) = match (&marker.recurrence, marker.start_time) {
+            (Some(recurrence), Some(start_time)) => (recurrence, start_time),
+            _ => return true,
+        };
+
+        let occurrences = recurrence.occurrences_between(start_time, marker.end_time, from, to);
+        if occurrences.is_empty() {
+            return false;
+        }
+        marker.occurrences = Some(sqlx::types::Json(occurrences));
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        let date = NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        DateTime::from_utc(date.and_hms_opt(10, 0, 0).unwrap(), Utc)
+    }
+
+    fn rule(
+        freq: Frequency,
+        interval: u32,
+        until: Option<DateTime<Utc>>,
+        count: Option<u32>,
+    ) -> Recurrence {
+        Recurrence {
+            freq,
+            interval,
+            until,
+            count,
+            exceptions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn stops_after_count() {
+        let first = date(2023, 5, 1);
+        let starts: Vec<_> = rule(Frequency::Daily, 2, None, Some(3))
+            .starts(first)
+            .collect();
+        assert_eq!(starts, [first, date(2023, 5, 3), date(2023, 5, 5)]);
+    }
+
+    #[test]
+    fn stops_at_until() {
+        let first = date(2023, 5, 1);
+        let starts: Vec<_> = rule(Frequency::Weekly, 1, Some(date(2023, 5, 15)), None)
+            .starts(first)
+            .collect();
+        assert_eq!(starts, [first, date(2023, 5, 8), date(2023, 5, 15)]);
+    }
+
+    #[test]
+    fn skips_months_without_the_day() {
+        let first = date(2023, 1, 31);
+        let starts: Vec<_> = rule(Frequency::Monthly, 1, None, Some(3))
+            .starts(first)
+            .collect();
+        assert_eq!(starts, [first, date(2023, 3, 31), date(2023, 5, 31)]);
+    }
+
+    #[test]
+    fn leaves_out_cancelled_occurrences() {
+        let first = date(2023, 5, 1);
+        let mut recurrence = rule(Frequency::Daily, 1, None, Some(3));
+        recurrence.exceptions.push(date(2023, 5, 2).timestamp());
+        let starts: Vec<_> = recurrence.starts(first).collect();
+        assert_eq!(starts, [first, date(2023, 5, 3)]);
+    }
+
+    #[test]
+    fn series_end_follows_the_last_occurrence() {
+        let first = date(2023, 5, 1);
+        let end = first + Duration::hours(2);
+        let recurrence = rule(Frequency::Daily, 1, None, Some(3));
+        assert_eq!(
+            recurrence.series_end(first, Some(end)),
+            Some(date(2023, 5, 3) + Duration::hours(2))
+        );
+        assert_eq!(
+            rule(Frequency::Daily, 1, None, None).series_end(first, Some(end)),
+            None
+        );
+    }
+
+    #[test]
+    fn occurrences_overlapping_the_window() {
+        let first = date(2023, 5, 1);
+        let recurrence = rule(Frequency::Daily, 1, None, None);
+        let occurrences = recurrence.occurrences_between(
+            first,
+            Some(first + Duration::hours(2)),
+            Some(date(2023, 5, 2) + Duration::hours(1)),
+            Some(date(2023, 5, 3)),
+        );
+        let starts: Vec<_> = occurrences.iter().map(|o| o.start_time).collect();
+        assert_eq!(starts, [date(2023, 5, 2), date(2023, 5, 3)]);
+    }
+
+    #[test]
+    fn endless_series_are_bounded() {
+        let recurrence = rule(Frequency::Daily, 1, None, None);
+        let occurrences = recurrence.occurrences_between(date(2023, 5, 1), None, None, None);
+        assert_eq!(occurrences.len(), MAX_EXPANDED);
+    }
+
+    #[test]
+    fn until_and_count_exclude_each_other() {
+        let recurrence = rule(Frequency::Daily, 1, Some(date(2023, 6, 1)), Some(3));
+        assert!(validate_recurrence(&recurrence).is_err());
+    }
+
+    #[test]
+    fn formats_rrule() {
+        let recurrence = rule(Frequency::Weekly, 2, None, Some(5));
+        assert_eq!(recurrence.to_rrule(), "FREQ=WEEKLY;INTERVAL=2;COUNT=5");
+        let recurrence = rule(Frequency::Monthly, 1, Some(date(2023, 6, 1)), None);
+        assert_eq!(
+            recurrence.to_rrule(),
+            "FREQ=MONTHLY;INTERVAL=1;UNTIL=20230601T100000Z"
+        );
+    }
+}
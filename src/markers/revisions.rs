@@ -0,0 +1,313 @@
+use chrono::serde::{ts_seconds, ts_seconds_option};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{MySql, Transaction};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{
+    attendance, categories, check_times, find_marker, ContactInfo, FullMarkerOwned, MarkerError,
+    Recurrence,
+};
+use crate::mail::Mailer;
+use crate::users::login::AddressOwned;
+
+/// Fields only the author and moderators may see the values of
+const PRIVATE_FIELDS: [&str; 1] = ["contactInfo"];
+/// Changing any of these lets the archiver judge the marker again
+const TIME_FIELDS: [&str; 3] = ["startTime", "endTime", "recurrence"];
+
+#[derive(Debug)]
+pub enum RevisionError {
+    NotFound,
+}
+
+impl fmt::Display for RevisionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "marker or revision not found"),
+        }
+    }
+}
+
+impl std::error::Error for RevisionError {}
+
+#[derive(Serialize, Deserialize)]
+pub struct Change {
+    old: Value,
+    new: Value,
+}
+
+/// One edit of a marker, keyed by the names the API uses for the fields.
+/// Changes of private fields are `null` for everyone but the author and moderators
+#[derive(Serialize)]
+pub struct Revision {
+    id: u32,
+    #[serde(rename = "userID")]
+    user_id: i32,
+    username: String,
+    changes: sqlx::types::Json<BTreeMap<String, Option<Change>>>,
+    #[serde(with = "ts_seconds")]
+    #[serde(rename = "addTime")]
+    add_time: DateTime<Utc>,
+}
+
+/// Every editable field of a marker, as found in a snapshot
+#[derive(Deserialize)]
+struct MarkerState {
+    latitude: f64,
+    longitude: f64,
+    title: String,
+    description: String,
+    #[serde(rename = "categoryID")]
+    category_id: u32,
+    #[serde(with = "ts_seconds_option")]
+    #[serde(rename = "startTime")]
+    start_time: Option<DateTime<Utc>>,
+    #[serde(with = "ts_seconds_option")]
+    #[serde(rename = "endTime")]
+    end_time: Option<DateTime<Utc>>,
+    recurrence: Option<Recurrence>,
+    capacity: Option<u32>,
+    address: AddressOwned,
+    #[serde(rename = "contactInfo")]
+    contact_info: ContactInfo,
+}
+
+/// Editable fields of the marker as they are now
+pub(crate) async fn snapshot(
+    tx: &mut Transaction<'_, MySql>,
+    marker_id: u32,
+) -> anyhow::Result<Map<String, Value>> {
+    let snapshot = sqlx::query!(
+        r#"
+        SELECT JSON_OBJECT(
+            "latitude", latitude, "longitude", longitude,
+            "title", title, "description", description, "categoryID", category_id,
+            "startTime", UNIX_TIMESTAMP(start_time), "endTime", UNIX_TIMESTAMP(end_time),
+            "recurrence", recurrence, "capacity", capacity,
+            "address", address, "contactInfo", contact_info
+        ) as `snapshot!: sqlx::types::Json<Map<String, Value>>`
+        FROM markers
+        WHERE id = ?
+        "#,
+        marker_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(RevisionError::NotFound)?
+    .snapshot;
+
+    Ok(snapshot.0)
+}
+
+/// Stores what the edit done by `user_id` changed since `before` was taken.
+/// Edits that changed nothing aren't stored
+pub(crate) async fn record(
+    tx: &mut Transaction<'_, MySql>,
+    marker_id: u32,
+    user_id: u32,
+    before: Map<String, Value>,
+) -> anyhow::Result<()> {
+    let mut after = snapshot(tx, marker_id).await?;
+    let changes: BTreeMap<String, Change> = before
+        .into_iter()
+        .filter_map(|(field, old)| {
+            let new = after.remove(&field).unwrap_or(Value::Null);
+            (old != new).then_some((field, Change { old, new }))
+        })
+        .collect();
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "INSERT INTO marker_revisions (marker_id, user_id, changes) VALUES (?, ?, ?)",
+        marker_id,
+        user_id,
+        serde_json::to_string(&changes)?
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Edits of the marker, latest first, its creation last. `full` reveals the values of private
+/// fields and the history of hidden markers and drafts, it's meant for authors and moderators
+pub async fn show_history(
+    db: &sqlx::MySqlPool,
+    marker_id: u32,
+    full: bool,
+) -> anyhow::Result<Vec<Revision>> {
+    sqlx::query!(
+        r#"
+        SELECT id FROM markers
+        WHERE id = ? AND deleted_at IS NULL AND ((hidden_at IS NULL AND NOT draft) OR ?)
+        "#,
+        marker_id,
+        full
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(RevisionError::NotFound)?;
+
+    let mut revisions = sqlx::query_as!(
+        Revision,
+        r#"
+        SELECT r.id, r.user_id, u.name as username,
+        r.changes as `changes: sqlx::types::Json<BTreeMap<String, Option<Change>>>`, r.add_time
+        FROM marker_revisions as r
+        INNER JOIN users as u ON u.id = r.user_id
+        WHERE r.marker_id = ?
+        ORDER BY r.id DESC
+        "#,
+        marker_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    if !full {
+        for revision in &mut revisions {
+            for field in PRIVATE_FIELDS {
+                if let Some(change) = revision.changes.0.get_mut(field) {
+                    *change = None;
+                }
+            }
+        }
+    }
+
+    Ok(revisions)
+}
+
+/// Whether the user wrote the marker and may see its full history
+pub async fn is_author(db: &sqlx::MySqlPool, user_id: u32, marker_id: u32) -> anyhow::Result<bool> {
+    let marker = sqlx::query!(
+        "SELECT id FROM markers WHERE id = ? AND user_id = ?",
+        marker_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(marker.is_some())
+}
+
+/// Stores the first version of a newly added marker, as a revision changing every field
+/// from `null`
+pub(crate) async fn record_creation(
+    tx: &mut Transaction<'_, MySql>,
+    marker_id: u32,
+    user_id: u32,
+) -> anyhow::Result<()> {
+    let before = snapshot(tx, marker_id)
+        .await?
+        .into_iter()
+        .map(|(field, _)| (field, Value::Null))
+        .collect();
+
+    record(tx, marker_id, user_id, before).await
+}
+
+/// Restores the marker to the state it was in right after the revision, undoing every later
+/// edit. The rollback is recorded as a revision of its own, so it can be undone as well
+pub async fn rollback_revision(
+    db: &sqlx::MySqlPool,
+    mailer: &Mailer,
+    moderator_id: u32,
+    marker_id: u32,
+    revision_id: u32,
+) -> anyhow::Result<FullMarkerOwned> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query!(
+        "SELECT id FROM markers WHERE id = ? AND deleted_at IS NULL FOR UPDATE",
+        marker_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(RevisionError::NotFound)?;
+
+    sqlx::query!(
+        "SELECT id FROM marker_revisions WHERE id = ? AND marker_id = ?",
+        revision_id,
+        marker_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(RevisionError::NotFound)?;
+
+    // Walking back from the current state, latest edit first
+    let later = sqlx::query!(
+        r#"
+        SELECT changes as `changes: sqlx::types::Json<BTreeMap<String, Change>>`
+        FROM marker_revisions
+        WHERE marker_id = ? AND id > ?
+        ORDER BY id DESC
+        "#,
+        marker_id,
+        revision_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let before = snapshot(&mut tx, marker_id).await?;
+    let mut state = before.clone();
+    for revision in later {
+        for (field, change) in revision.changes.0 {
+            state.insert(field, change.old);
+        }
+    }
+    let times_changed = TIME_FIELDS
+        .iter()
+        .any(|field| state.get(*field) != before.get(*field));
+    let state: MarkerState = serde_json::from_value(Value::Object(state))?;
+
+    let series_end = check_times(state.start_time, state.end_time, state.recurrence.as_ref())?;
+    if !categories::category_exists(&mut tx, state.category_id).await? {
+        return Err(MarkerError::UnknownCategory.into());
+    }
+    let recurrence = match &state.recurrence {
+        Some(recurrence) => Some(serde_json::to_string(recurrence)?),
+        None => None,
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE markers SET
+        latitude = ?, longitude = ?, title = ?, description = ?, category_id = ?,
+        start_time = ?, end_time = ?, recurrence = ?, series_end = ?, capacity = ?,
+        address = ?, contact_info = ?,
+        archived_at = IF(?, NULL, archived_at)
+        WHERE id = ?
+        "#,
+        state.latitude,
+        state.longitude,
+        state.title,
+        state.description,
+        state.category_id,
+        state.start_time,
+        state.end_time,
+        recurrence,
+        series_end,
+        state.capacity,
+        serde_json::to_string(&state.address)?,
+        serde_json::to_string(&state.contact_info)?,
+        times_changed,
+        marker_id
+    )
+    .execute(&mut tx)
+    .await?;
+    record(&mut tx, marker_id, moderator_id, before).await?;
+    let promoted = attendance::fill_capacity(&mut tx, marker_id).await?;
+
+    let marker = find_marker(&mut tx, marker_id, None)
+        .await?
+        .ok_or(MarkerError::NotFound)?;
+
+    tx.commit().await?;
+    attendance::notify_promoted(mailer, &marker.title, promoted).await;
+
+    Ok(marker)
+}
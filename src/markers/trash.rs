@@ -0,0 +1,144 @@
+use chrono::{Duration, Utc};
+use sqlx::QueryBuilder;
+
+use super::{find_marker, images, FullMarkerOwned, MarkerError, MARKER_COLUMNS};
+
+/// How long deleted markers can be restored before they are purged for good
+pub const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+/// Deleted markers of the user, most recently deleted first
+pub async fn show_trash(
+    db: &sqlx::MySqlPool,
+    user_id: u32,
+) -> anyhow::Result<Vec<FullMarkerOwned>> {
+    let mut query = QueryBuilder::new(MARKER_COLUMNS);
+    query
+        .push(" AND user_id = ")
+        .push_bind(user_id)
+        .push(" AND deleted_at IS NOT NULL ORDER BY deleted_at DESC, id DESC");
+
+    let markers = query.build_query_as().fetch_all(db).await?;
+
+    Ok(markers)
+}
+
+/// Takes the marker out of the trash, with its attendees, comments and images intact
+pub async fn restore_deleted_marker(
+    db: &sqlx::MySqlPool,
+    user_id: u32,
+    marker_id: u32,
+) -> anyhow::Result<FullMarkerOwned> {
+    let mut tx = db.begin().await?;
+
+    let restored = sqlx::query!(
+        "UPDATE markers SET deleted_at = NULL WHERE id = ? AND user_id = ? AND deleted_at IS NOT NULL",
+        marker_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+    if restored.rows_affected() == 0 {
+        return Err(MarkerError::NotFound.into());
+    }
+
+    let marker = find_marker(&mut tx, marker_id, Some(user_id))
+        .await?
+        .ok_or(MarkerError::NotFound)?;
+
+    tx.commit().await?;
+
+    Ok(marker)
+}
+
+/// Permanently deletes markers that spent longer than `retention` in the trash, returns
+/// how many of them were purged
+pub async fn purge_deleted_markers(
+    db: &sqlx::MySqlPool,
+    retention: Duration,
+) -> anyhow::Result<u64> {
+    let cutoff = Utc::now() - retention;
+    let expired = sqlx::query!("SELECT id FROM markers WHERE deleted_at < ?", cutoff)
+        .fetch_all(db)
+        .await?;
+
+    let mut purged = 0;
+    for marker in expired {
+        // Markers restored in the meantime stay, and so do the ones trashed again since
+        let deleted = sqlx::query!(
+            "DELETE FROM markers WHERE id = ? AND deleted_at < ?",
+            marker.id,
+            cutoff
+        )
+        .execute(db)
+        .await?;
+        if deleted.rows_affected() > 0 {
+            images::remove_marker_images(marker.id).await;
+            purged += 1;
+        }
+    }
+
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markers::{delete_marker, show_markers, MarkerFilter, PageRequest};
+    use crate::testing;
+    use sqlx::MySqlPool;
+
+    async fn listed(db: &MySqlPool) -> anyhow::Result<Vec<u64>> {
+        let page = show_markers(db, MarkerFilter::default(), PageRequest::first()).await?;
+        testing::ids(page)
+    }
+
+    async fn trashed(db: &MySqlPool, user_id: u32) -> anyhow::Result<Vec<u32>> {
+        let trash = show_trash(db, user_id).await?;
+        Ok(trash.iter().map(|marker| marker.id).collect())
+    }
+
+    #[sqlx::test]
+    async fn restores_markers_from_the_trash(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let jan = testing::add_user(&db, "jan").await?;
+        let anna = testing::add_user(&db, "anna").await?;
+        let first = testing::add_marker(&db, jan, "Zakupy").await?;
+        let second = testing::add_marker(&db, jan, "Spacer").await?;
+
+        delete_marker(&db, jan, first).await?;
+        assert!(delete_marker(&db, anna, second).await.is_err());
+        delete_marker(&db, jan, second).await?;
+        assert!(listed(&db).await?.is_empty());
+        let mut trash = trashed(&db, jan).await?;
+        trash.sort_unstable();
+        assert_eq!(trash, [first, second]);
+        assert!(trashed(&db, anna).await?.is_empty());
+
+        assert!(restore_deleted_marker(&db, anna, first).await.is_err());
+        restore_deleted_marker(&db, jan, first).await?;
+        assert!(restore_deleted_marker(&db, jan, first).await.is_err());
+        assert_eq!(listed(&db).await?, [first as u64]);
+        assert_eq!(trashed(&db, jan).await?, [second]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn keeps_markers_for_the_retention(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let jan = testing::add_user(&db, "jan").await?;
+        let marker = testing::add_marker(&db, jan, "Zakupy").await?;
+        delete_marker(&db, jan, marker).await?;
+        sqlx::query("UPDATE markers SET deleted_at = ? WHERE id = ?")
+            .bind(Utc::now() - Duration::days(29))
+            .bind(marker)
+            .execute(&db)
+            .await?;
+
+        let retention = Duration::days(DEFAULT_RETENTION_DAYS);
+        assert_eq!(purge_deleted_markers(&db, retention).await?, 0);
+        assert_eq!(trashed(&db, jan).await?, [marker]);
+
+        Ok(())
+    }
+}
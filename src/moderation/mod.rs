@@ -0,0 +1,350 @@
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use rocket::error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use validator::Validate;
+
+use crate::mail::Mailer;
+use crate::markers::pagination::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::users::reputation::{self, ReputationReason};
+
+/// Markers reported by this many distinct users with positive reputation get hidden
+/// until a moderator looks at them
+pub const DEFAULT_AUTO_HIDE_REPORTS: i64 = 3;
+
+#[derive(Debug)]
+pub enum ModerationError {
+    NotFound,
+    OwnMarker,
+    AlreadyReported,
+}
+
+impl fmt::Display for ModerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "marker not found"),
+            Self::OwnMarker => write!(f, "users can't report their own markers"),
+            Self::AlreadyReported => write!(f, "user's report of the marker is still open"),
+        }
+    }
+}
+
+impl std::error::Error for ModerationError {}
+
+#[derive(Deserialize, Validate)]
+pub struct Report<'r> {
+    #[validate(length(min = 1, max = 500))]
+    reason: &'r str,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct Warning<'r> {
+    #[validate(length(min = 1, max = 500))]
+    reason: &'r str,
+}
+
+/// Marker waiting for a moderator, with the reasons of its unresolved reports
+#[derive(Serialize)]
+pub struct ReportedMarker {
+    #[serde(rename = "markerID")]
+    marker_id: u32,
+    title: String,
+    #[serde(rename = "userID")]
+    user_id: i32,
+    hidden: bool,
+    reports: i64,
+    reasons: sqlx::types::Json<Vec<String>>,
+    #[serde(with = "ts_seconds")]
+    #[serde(rename = "lastReportTime")]
+    last_report_time: DateTime<Utc>,
+}
+
+/// Rights granted to users in the database
+#[derive(Clone, Copy)]
+pub enum Role {
+    Moderator,
+    /// Admins are moderators as well
+    Admin,
+}
+
+pub async fn has_role(db: &sqlx::MySqlPool, user_id: u32, role: Role) -> anyhow::Result<bool> {
+    let user = sqlx::query!(
+        "SELECT moderator as `moderator: bool`, admin as `admin: bool` FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(user.map_or(false, |user| match role {
+        Role::Moderator => user.moderator || user.admin,
+        Role::Admin => user.admin,
+    }))
+}
+
+impl Report<'_> {
+    /// Files the report and hides the marker once `auto_hide_reports` users with positive
+    /// reputation have reported it
+    pub async fn report_marker(
+        &self,
+        db: &sqlx::MySqlPool,
+        user_id: u32,
+        marker_id: u32,
+        auto_hide_reports: i64,
+    ) -> anyhow::Result<()> {
+        let mut tx = db.begin().await?;
+
+        let marker = sqlx::query!(
+            r#"
+            SELECT user_id FROM markers
+            WHERE id = ? AND hidden_at IS NULL AND deleted_at IS NULL AND NOT draft
+            FOR UPDATE
+            "#,
+            marker_id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(ModerationError::NotFound)?;
+        if marker.user_id as u32 == user_id {
+            return Err(ModerationError::OwnMarker.into());
+        }
+
+        // Only open reports are unique, a resolved one doesn't keep the user from reporting again
+        let reported = sqlx::query!(
+            "INSERT IGNORE INTO reports (marker_id, user_id, reason) VALUES (?, ?, ?)",
+            marker_id,
+            user_id,
+            self.reason
+        )
+        .execute(&mut tx)
+        .await?;
+        if reported.rows_affected() == 0 {
+            return Err(ModerationError::AlreadyReported.into());
+        }
+
+        let trusted_reports = sqlx::query!(
+            r#"
+            SELECT COUNT(*) as reports
+            FROM reports as r
+            INNER JOIN full_users_info as ext ON ext.id = r.user_id
+            WHERE r.marker_id = ? AND r.resolved_at IS NULL AND ext.reputation > 0
+            "#,
+            marker_id
+        )
+        .fetch_one(&mut tx)
+        .await?
+        .reports;
+
+        if trusted_reports >= auto_hide_reports {
+            sqlx::query!(
+                "UPDATE markers SET hidden_at = ? WHERE id = ? AND hidden_at IS NULL",
+                Utc::now(),
+                marker_id
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Markers with unresolved reports, the most reported first
+pub async fn show_report_queue(
+    db: &sqlx::MySqlPool,
+    limit: Option<u32>,
+) -> anyhow::Result<Vec<ReportedMarker>> {
+    let markers = sqlx::query_as!(
+        ReportedMarker,
+        r#"
+        SELECT m.id as marker_id, m.title, m.user_id,
+        m.hidden_at IS NOT NULL as `hidden: bool`,
+        COUNT(*) as reports,
+        JSON_ARRAYAGG(r.reason) as `reasons!: sqlx::types::Json<Vec<String>>`,
+        MAX(r.add_time) as `last_report_time!: DateTime<Utc>`
+        FROM reports as r
+        INNER JOIN markers as m ON m.id = r.marker_id
+        WHERE r.resolved_at IS NULL AND m.deleted_at IS NULL
+        GROUP BY m.id
+        ORDER BY reports DESC, MAX(r.add_time) DESC
+        LIMIT ?
+        "#,
+        limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(markers)
+}
+
+/// Marks the open reports of the marker as dealt with
+async fn resolve_reports<'e>(
+    executor: impl sqlx::MySqlExecutor<'e>,
+    marker_id: u32,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE reports SET resolved_at = ? WHERE marker_id = ? AND resolved_at IS NULL",
+        Utc::now(),
+        marker_id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Hides the marker from everyone but its author, or brings it back when `hidden` is false.
+/// Either way the reports are resolved
+pub async fn set_hidden(db: &sqlx::MySqlPool, marker_id: u32, hidden: bool) -> anyhow::Result<()> {
+    let mut tx = db.begin().await?;
+
+    let updated = sqlx::query!(
+        "UPDATE markers SET hidden_at = IF(?, COALESCE(hidden_at, ?), NULL) WHERE id = ?",
+        hidden,
+        Utc::now(),
+        marker_id
+    )
+    .execute(&mut tx)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Err(ModerationError::NotFound.into());
+    }
+    resolve_reports(&mut tx, marker_id).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+impl Warning<'_> {
+    /// Records the warning, takes reputation away from the author of the marker and emails them
+    pub async fn warn_author(
+        &self,
+        db: &sqlx::MySqlPool,
+        mailer: &Mailer,
+        moderator_id: u32,
+        marker_id: u32,
+    ) -> anyhow::Result<()> {
+        let mut tx = db.begin().await?;
+
+        let author = sqlx::query!(
+            r#"
+            SELECT m.user_id, m.title, u.email
+            FROM markers as m
+            INNER JOIN users as u ON u.id = m.user_id
+            WHERE m.id = ?
+            "#,
+            marker_id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(ModerationError::NotFound)?;
+
+        sqlx::query!(
+            "INSERT INTO warnings (user_id, moderator_id, marker_id, reason) VALUES (?, ?, ?, ?)",
+            author.user_id,
+            moderator_id,
+            marker_id,
+            self.reason
+        )
+        .execute(&mut tx)
+        .await?;
+        reputation::record(
+            &mut tx,
+            author.user_id as u32,
+            ReputationReason::Penalty,
+            Some(marker_id),
+            Some(moderator_id),
+        )
+        .await?;
+        resolve_reports(&mut tx, marker_id).await?;
+
+        tx.commit().await?;
+
+        let body = format!(
+            "Moderator przesłał ostrzeżenie dotyczące znacznika „{}”:\n\n{}",
+            author.title, self.reason
+        );
+        if let Err(e) = mailer
+            .send(&author.email, "Ostrzeżenie od moderatora", body)
+            .await
+        {
+            error!("Failed to send the warning to {}: {}", author.email, e);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markers::{show_markers, MarkerFilter, PageRequest};
+    use crate::testing;
+    use sqlx::MySqlPool;
+
+    async fn report(db: &MySqlPool, user_id: u32, marker_id: u32) -> anyhow::Result<()> {
+        Report { reason: "Spam" }
+            .report_marker(db, user_id, marker_id, 2)
+            .await
+    }
+
+    async fn listed(db: &MySqlPool) -> anyhow::Result<Vec<u64>> {
+        let page = show_markers(db, MarkerFilter::default(), PageRequest::first()).await?;
+        testing::ids(page)
+    }
+
+    fn moderation_error(result: anyhow::Result<()>) -> Option<ModerationError> {
+        result.err()?.downcast().ok()
+    }
+
+    #[sqlx::test]
+    async fn hides_markers_reported_by_trusted_users(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let jan = testing::add_user(&db, "jan").await?;
+        let anna = testing::add_user(&db, "anna").await?;
+        let piotr = testing::add_user(&db, "piotr").await?;
+        let newcomer = testing::add_user(&db, "ewa").await?;
+        sqlx::query("UPDATE full_users_info SET reputation = 1 WHERE id IN (?, ?)")
+            .bind(anna)
+            .bind(piotr)
+            .execute(&db)
+            .await?;
+        let marker = testing::add_marker(&db, jan, "Zakupy").await?;
+
+        let own = report(&db, jan, marker).await;
+        assert!(matches!(
+            moderation_error(own),
+            Some(ModerationError::OwnMarker)
+        ));
+        report(&db, anna, marker).await?;
+        let again = report(&db, anna, marker).await;
+        assert!(matches!(
+            moderation_error(again),
+            Some(ModerationError::AlreadyReported)
+        ));
+        // Reports of users without reputation are queued but don't hide anything
+        report(&db, newcomer, marker).await?;
+        assert_eq!(listed(&db).await?, [marker as u64]);
+        report(&db, piotr, marker).await?;
+        assert!(listed(&db).await?.is_empty());
+
+        let queue = show_report_queue(&db, None).await?;
+        assert_eq!(queue.len(), 1);
+        assert_eq!((queue[0].marker_id, queue[0].reports), (marker, 3));
+        assert!(queue[0].hidden);
+
+        set_hidden(&db, marker, false).await?;
+        assert_eq!(listed(&db).await?, [marker as u64]);
+        assert!(show_report_queue(&db, None).await?.is_empty());
+
+        // Resolved reports don't count as duplicates
+        report(&db, anna, marker).await?;
+        let queue = show_report_queue(&db, None).await?;
+        assert_eq!((queue[0].reports, queue[0].hidden), (1, false));
+
+        Ok(())
+    }
+}
@@ -1,23 +1,73 @@
+use crate::jobs::env_or;
+use crate::mail::Mailer;
 use crate::markers::*;
+use crate::moderation::*;
 use crate::users::login::*;
 use crate::users::register::*;
+use crate::users::reputation::*;
 use crate::*;
 use crate::{SomsiadResult, SomsiadStatus};
 use rocket::{
-    catch, delete, error_, get,
+    catch, delete, error_,
+    form::Form,
+    fs::NamedFile,
+    get,
     http::{Cookie, CookieJar, Method, Status},
-    info_, post, put,
+    info_, patch, post, put,
     serde::json::Json,
     warn_, Request,
 };
 use sqlx::MySqlPool;
+use validator::ValidationErrors;
 use validator::ValidationErrorsKind::*;
 
+/// Paths of the invalid fields, nested ones joined with dots. Errors of whole structs
+/// carry the name of the field at fault as their code
+fn invalid_fields(e: ValidationErrors) -> Vec<String> {
+    let mut fields = Vec::new();
+    collect_invalid_fields(&e, "", &mut fields);
+    fields
+}
+
+/// Names of the invalid fields without the structs they are nested in, as registration has
+/// always reported them
+fn flat_invalid_fields(e: ValidationErrors) -> Vec<String> {
+    e.errors()
+        .iter()
+        .flat_map(|(field, err_kinds)| match err_kinds {
+            Struct(err) => err.errors().keys().map(|field| field.to_string()).collect(),
+            _ => vec![field.to_string()],
+        })
+        .collect()
+}
+
+fn collect_invalid_fields(e: &ValidationErrors, prefix: &str, fields: &mut Vec<String>) {
+    for (field, err_kinds) in e.errors() {
+        match err_kinds {
+            Struct(err) => collect_invalid_fields(err, &format!("{}{}.", prefix, field), fields),
+            List(errs) => {
+                for (i, err) in errs {
+                    collect_invalid_fields(err, &format!("{}{}[{}].", prefix, field, i), fields)
+                }
+            }
+            Field(errs) if *field == "__all__" => {
+                fields.extend(errs.iter().map(|err| format!("{}{}", prefix, err.code)))
+            }
+            Field(_) => fields.push(format!("{}{}", prefix, field)),
+        }
+    }
+}
+
 #[catch(401)]
 pub fn unauthorized_catcher() -> SomsiadResult<&'static str> {
     SomsiadStatus::error("Nie jesteś zalogowany")
 }
 
+#[catch(403)]
+pub fn forbidden_catcher() -> SomsiadResult<&'static str> {
+    SomsiadStatus::error("Nie masz uprawnień do tej operacji")
+}
+
 #[catch(404)]
 pub fn options_catcher<'a>(status: Status, request: &Request) -> (Status, SomsiadResult<&'a str>) {
     if request.method() == Method::Options {
@@ -35,12 +85,37 @@ pub async fn is_logged(_user: UserID) -> SomsiadResult<&'static str> {
     SomsiadStatus::ok("Jesteś zalogowany")
 }
 
-#[get("/user_markers")]
+#[get("/user_markers?<cursor>&<limit>&<filter..>")]
 pub async fn get_user_markers(
     db: &rocket::State<MySqlPool>,
     user_id: UserID,
+    cursor: Option<&str>,
+    limit: Option<u32>,
+    filter: MarkerFilter<'_>,
+    geojson: AcceptsGeoJson,
+) -> Result<Listing<Page<FullMarkerOwned>>, SomsiadResult<()>> {
+    let page = match PageRequest::new(cursor, limit) {
+        Some(page) => page,
+        None => return Err(SomsiadStatus::error("Nieprawidłowy kursor")),
+    };
+    match show_user_markers(db, user_id.0, filter, page)
+        .await
+        .and_then(|markers| Ok(Listing::from_page(geojson, markers)?))
+    {
+        Ok(listing) => Ok(listing),
+        Err(e) => {
+            error_!("Error: {}", e);
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
+        }
+    }
+}
+
+#[get("/user_markers/trash")]
+pub async fn get_user_trash(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
 ) -> SomsiadResult<Vec<FullMarkerOwned>> {
-    match show_user_markers(db, user_id.0).await {
+    match show_trash(db, user_id.0).await {
         Ok(markers) => SomsiadStatus::ok(markers),
         Err(e) => {
             error_!("Error: {}", e);
@@ -49,42 +124,369 @@ pub async fn get_user_markers(
     }
 }
 
-#[get("/markers/<city>", rank = 2)]
+#[post("/markers/<marker_id>/restore")]
+pub async fn restore_user_marker(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<FullMarkerOwned> {
+    match restore_deleted_marker(db, user_id.0, marker_id).await {
+        Err(e) => match e.downcast_ref::<MarkerError>() {
+            Some(MarkerError::NotFound) => {
+                SomsiadStatus::error("Znacznik nie istnieje w twoim koszu")
+            }
+            _ => {
+                error_!("Error in restore_user_marker: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(marker) => SomsiadStatus::ok(marker),
+    }
+}
+
+#[post("/markers/<marker_id>/publish")]
+pub async fn publish_user_marker(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<FullMarkerOwned> {
+    match publish_draft(db, user_id.0, marker_id).await {
+        Err(e) => match e.downcast_ref::<MarkerError>() {
+            Some(MarkerError::NotFound) => {
+                SomsiadStatus::error("Znacznik nie istnieje wśród twoich szkiców")
+            }
+            _ => {
+                error_!("Error in publish_user_marker: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(marker) => SomsiadStatus::ok(marker),
+    }
+}
+
+#[get("/markers/id/<id>")]
+pub async fn get_marker(
+    db: &rocket::State<MySqlPool>,
+    id: u32,
+) -> (Status, SomsiadResult<FullMarkerOwned>) {
+    match show_marker(db, id).await {
+        Ok(Some(marker)) => (Status::Ok, SomsiadStatus::ok(marker)),
+        Ok(None) => (
+            Status::NotFound,
+            SomsiadStatus::error("Znacznik o podanym id nie istnieje"),
+        ),
+        Err(e) => {
+            error_!("Error: {}", e);
+            (
+                Status::InternalServerError,
+                SomsiadStatus::error("Wewnętrzny błąd serwera"),
+            )
+        }
+    }
+}
+
+#[get("/markers/search?<q>&<limit>&<filter..>")]
+pub async fn get_markers_by_phrase(
+    db: &rocket::State<MySqlPool>,
+    q: &str,
+    limit: Option<u32>,
+    filter: MarkerFilter<'_>,
+    geojson: AcceptsGeoJson,
+) -> Result<Listing<SomsiadResult<Vec<FullMarkerOwned>>>, SomsiadResult<()>> {
+    if q.trim().is_empty() {
+        return Err(SomsiadStatus::error("Podaj frazę do wyszukania"));
+    }
+    match search_markers(db, q, filter, limit)
+        .await
+        .and_then(|markers| Ok(Listing::from_markers(geojson, markers)?))
+    {
+        Ok(listing) => Ok(listing),
+        Err(e) => {
+            error_!("Error: {}", e);
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
+        }
+    }
+}
+
+#[get("/markers/bbox?<south>&<west>&<north>&<east>&<cursor>&<limit>&<filter..>")]
+pub async fn get_markers_in_bbox(
+    db: &rocket::State<MySqlPool>,
+    south: f64,
+    west: f64,
+    north: f64,
+    east: f64,
+    cursor: Option<&str>,
+    limit: Option<u32>,
+    filter: MarkerFilter<'_>,
+    geojson: AcceptsGeoJson,
+) -> Result<Listing<Page<FullMarkerOwned>>, SomsiadResult<()>> {
+    let bbox = match BoundingBox::new(south, west, north, east) {
+        Ok(bbox) => bbox,
+        Err(e) => return Err(SomsiadStatus::error(e)),
+    };
+    let page = match PageRequest::new(cursor, limit) {
+        Some(page) => page,
+        None => return Err(SomsiadStatus::error("Nieprawidłowy kursor")),
+    };
+    match show_markers_in_bbox(db, &bbox, filter, page)
+        .await
+        .and_then(|markers| Ok(Listing::from_page(geojson, markers)?))
+    {
+        Ok(listing) => Ok(listing),
+        Err(e) => {
+            error_!("Error: {}", e);
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
+        }
+    }
+}
+
+#[get("/markers/clusters?<south>&<west>&<north>&<east>&<zoom>&<filter..>")]
+pub async fn get_marker_clusters(
+    db: &rocket::State<MySqlPool>,
+    south: f64,
+    west: f64,
+    north: f64,
+    east: f64,
+    zoom: u8,
+    filter: MarkerFilter<'_>,
+) -> SomsiadResult<Clusters> {
+    let bbox = match BoundingBox::with_max_span(south, west, north, east, MAX_CLUSTER_SPAN) {
+        Ok(bbox) => bbox,
+        Err(e) => return SomsiadStatus::error(e),
+    };
+    match cluster_markers(db, &bbox, zoom, filter).await {
+        Ok(clusters) => SomsiadStatus::ok(clusters),
+        Err(e) => {
+            error_!("Error: {}", e);
+            SomsiadStatus::error("Wewnętrzny błąd serwera")
+        }
+    }
+}
+
+#[get("/markers/<city>?<cursor>&<limit>&<filter..>", rank = 2)]
 pub async fn get_markers_by_city(
     db: &rocket::State<MySqlPool>,
     city: &str,
-) -> SomsiadResult<Vec<FullMarkerOwned>> {
-    match show_markers_by_city(db, city).await {
-        Ok(markers) => SomsiadStatus::ok(markers),
+    cursor: Option<&str>,
+    limit: Option<u32>,
+    filter: MarkerFilter<'_>,
+    geojson: AcceptsGeoJson,
+) -> Result<Listing<Page<FullMarkerOwned>>, SomsiadResult<()>> {
+    let page = match PageRequest::new(cursor, limit) {
+        Some(page) => page,
+        None => return Err(SomsiadStatus::error("Nieprawidłowy kursor")),
+    };
+    match show_markers_by_city(db, city, filter, page)
+        .await
+        .and_then(|markers| Ok(Listing::from_page(geojson, markers)?))
+    {
+        Ok(listing) => Ok(listing),
         Err(e) => {
             error_!("Error: {}", e);
-            SomsiadStatus::error("Wewnętrzny błąd serwera")
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
         }
     }
 }
 
-#[get("/markers?<lat>&<long>&<dist>")]
+#[get("/markers?<lat>&<long>&<dist>&<cursor>&<limit>&<filter..>")]
 pub async fn get_markers_by_dist(
     db: &rocket::State<MySqlPool>,
     lat: f64,
     long: f64,
     dist: u32,
-) -> SomsiadResult<Vec<FullMarkerOwnedWithDist>> {
-    match show_markers_by_dist(db, lat, long, dist).await {
-        Ok(markers) => SomsiadStatus::ok(markers),
+    cursor: Option<&str>,
+    limit: Option<u32>,
+    filter: MarkerFilter<'_>,
+    geojson: AcceptsGeoJson,
+) -> Result<Listing<Page<FullMarkerOwnedWithDist>>, SomsiadResult<()>> {
+    let page = match PageRequest::nearest(cursor, limit) {
+        Some(page) => page,
+        None => return Err(SomsiadStatus::error("Nieprawidłowy kursor")),
+    };
+    match show_markers_by_dist(db, lat, long, dist, filter, page)
+        .await
+        .and_then(|markers| Ok(Listing::from_page(geojson, markers)?))
+    {
+        Ok(listing) => Ok(listing),
         Err(e) => {
             error_!("Error: {}", e);
-            SomsiadStatus::error("Wewnętrzny błąd serwera")
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
         }
     }
 }
-#[get("/markers")]
-pub async fn get_markers(db: &rocket::State<MySqlPool>) -> SomsiadResult<Vec<FullMarkerOwned>> {
-    match show_markers(db).await {
-        Ok(markers) => SomsiadStatus::ok(markers),
+#[get("/markers?<cursor>&<limit>&<filter..>", rank = 2)]
+pub async fn get_markers(
+    db: &rocket::State<MySqlPool>,
+    cursor: Option<&str>,
+    limit: Option<u32>,
+    filter: MarkerFilter<'_>,
+    geojson: AcceptsGeoJson,
+) -> Result<Listing<Page<FullMarkerOwned>>, SomsiadResult<()>> {
+    let page = match PageRequest::new(cursor, limit) {
+        Some(page) => page,
+        None => return Err(SomsiadStatus::error("Nieprawidłowy kursor")),
+    };
+    match show_markers(db, filter, page)
+        .await
+        .and_then(|markers| Ok(Listing::from_page(geojson, markers)?))
+    {
+        Ok(listing) => Ok(listing),
         Err(e) => {
             error_!("Error: {}", e);
-            SomsiadStatus::error("Wewnętrzny błąd serwera")
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
+        }
+    }
+}
+
+#[get("/markers.geojson?<cursor>&<limit>&<filter..>")]
+pub async fn get_markers_geojson(
+    db: &rocket::State<MySqlPool>,
+    cursor: Option<&str>,
+    limit: Option<u32>,
+    filter: MarkerFilter<'_>,
+) -> Result<GeoJson, SomsiadResult<()>> {
+    let page = match PageRequest::new(cursor, limit) {
+        Some(page) => page,
+        None => return Err(SomsiadStatus::error("Nieprawidłowy kursor")),
+    };
+    match show_markers(db, filter, page)
+        .await
+        .and_then(|markers| Ok(GeoJson::from_page(markers)?))
+    {
+        Ok(geojson) => Ok(geojson),
+        Err(e) => {
+            error_!("Error: {}", e);
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
+        }
+    }
+}
+
+#[get("/markers/bbox.geojson?<south>&<west>&<north>&<east>&<cursor>&<limit>&<filter..>")]
+pub async fn get_markers_in_bbox_geojson(
+    db: &rocket::State<MySqlPool>,
+    south: f64,
+    west: f64,
+    north: f64,
+    east: f64,
+    cursor: Option<&str>,
+    limit: Option<u32>,
+    filter: MarkerFilter<'_>,
+) -> Result<GeoJson, SomsiadResult<()>> {
+    let bbox = match BoundingBox::new(south, west, north, east) {
+        Ok(bbox) => bbox,
+        Err(e) => return Err(SomsiadStatus::error(e)),
+    };
+    let page = match PageRequest::new(cursor, limit) {
+        Some(page) => page,
+        None => return Err(SomsiadStatus::error("Nieprawidłowy kursor")),
+    };
+    match show_markers_in_bbox(db, &bbox, filter, page)
+        .await
+        .and_then(|markers| Ok(GeoJson::from_page(markers)?))
+    {
+        Ok(geojson) => Ok(geojson),
+        Err(e) => {
+            error_!("Error: {}", e);
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
+        }
+    }
+}
+
+#[get("/user_markers.geojson?<cursor>&<limit>&<filter..>")]
+pub async fn get_user_markers_geojson(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    cursor: Option<&str>,
+    limit: Option<u32>,
+    filter: MarkerFilter<'_>,
+) -> Result<GeoJson, SomsiadResult<()>> {
+    let page = match PageRequest::new(cursor, limit) {
+        Some(page) => page,
+        None => return Err(SomsiadStatus::error("Nieprawidłowy kursor")),
+    };
+    match show_user_markers(db, user_id.0, filter, page)
+        .await
+        .and_then(|markers| Ok(GeoJson::from_page(markers)?))
+    {
+        Ok(geojson) => Ok(geojson),
+        Err(e) => {
+            error_!("Error: {}", e);
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
+        }
+    }
+}
+
+#[get("/markers/<file>", rank = 1)]
+pub async fn get_marker_ics(
+    db: &rocket::State<MySqlPool>,
+    file: IcsFile,
+) -> Result<Calendar, (Status, SomsiadResult<()>)> {
+    match show_marker(db, file.0).await {
+        Ok(Some(marker)) => Calendar::from_marker(&marker).ok_or_else(|| {
+            (
+                Status::NotFound,
+                SomsiadStatus::error("Wydarzenie nie ma określonego terminu"),
+            )
+        }),
+        Ok(None) => Err((
+            Status::NotFound,
+            SomsiadStatus::error("Znacznik o podanym id nie istnieje"),
+        )),
+        Err(e) => {
+            error_!("Error: {}", e);
+            Err((
+                Status::InternalServerError,
+                SomsiadStatus::error("Wewnętrzny błąd serwera"),
+            ))
+        }
+    }
+}
+
+#[get("/markers.ics?<filter..>")]
+pub async fn get_markers_ics(
+    db: &rocket::State<MySqlPool>,
+    filter: MarkerFilter<'_>,
+) -> Result<Calendar, SomsiadResult<()>> {
+    if !filter.is_local() {
+        return Err(SomsiadStatus::error(
+            "Podaj miasto albo współrzędne i promień",
+        ));
+    }
+    match show_scheduled_markers(db, filter).await {
+        Ok(markers) => Ok(Calendar::from_markers("CoSięDzieje", &markers)),
+        Err(e) => {
+            error_!("Error: {}", e);
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
+        }
+    }
+}
+
+#[get("/markers/<city>/atom?<filter..>")]
+pub async fn get_city_atom_feed(
+    db: &rocket::State<MySqlPool>,
+    city: &str,
+    filter: MarkerFilter<'_>,
+) -> Result<AtomFeed, SomsiadResult<()>> {
+    match show_markers_by_city(db, city, filter, PageRequest::first()).await {
+        Ok(markers) => Ok(AtomFeed::from_markers(city, &markers.into_parts().0)),
+        Err(e) => {
+            error_!("Error: {}", e);
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
+        }
+    }
+}
+
+#[get("/markers/<city>/rss?<filter..>")]
+pub async fn get_city_rss_feed(
+    db: &rocket::State<MySqlPool>,
+    city: &str,
+    filter: MarkerFilter<'_>,
+) -> Result<RssFeed, SomsiadResult<()>> {
+    match show_markers_by_city(db, city, filter, PageRequest::first()).await {
+        Ok(markers) => Ok(RssFeed::from_markers(city, &markers.into_parts().0)),
+        Err(e) => {
+            error_!("Error: {}", e);
+            Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
         }
     }
 }
@@ -95,11 +497,25 @@ pub async fn add_marker(
     marker: Json<FullMarker<'_>>,
     user_id: UserID,
 ) -> SomsiadResult<()> {
+    if let Err(e) = marker.validate() {
+        return SomsiadStatus::errors(invalid_fields(e));
+    }
     match marker.add_marker(db, user_id.0).await {
-        Err(e) => {
-            error_!("Internal error: {}", e);
-            SomsiadStatus::error("Nieoczekiwany błąd")
-        }
+        Err(e) => match e.downcast_ref::<MarkerError>() {
+            Some(MarkerError::EndBeforeStart) => {
+                SomsiadStatus::error("Wydarzenie nie może kończyć się przed rozpoczęciem")
+            }
+            Some(MarkerError::RecurrenceWithoutStart) => {
+                SomsiadStatus::error("Wydarzenie cykliczne musi mieć czas rozpoczęcia")
+            }
+            Some(MarkerError::UnknownCategory) => {
+                SomsiadStatus::error("Wybrana kategoria nie istnieje")
+            }
+            _ => {
+                error_!("Internal error: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
         Ok(false) => {
             warn_!("Zero rows affected, user not added");
             SomsiadStatus::error("Nieoczekiwany błąd")
@@ -115,33 +531,722 @@ pub async fn remove_marker(
     marker_id: u32,
 ) -> SomsiadResult<FullMarkerOwned> {
     match delete_marker(db, user_id.0, marker_id).await {
+        Err(e) => match e.downcast_ref::<MarkerError>() {
+            Some(MarkerError::NotFound) => {
+                SomsiadStatus::error("Znacznik nie istnieje lub nie należy do ciebie")
+            }
+            _ => {
+                error_!("Error in remove_marker: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(marker) => SomsiadStatus::ok(marker),
+    }
+}
+
+#[patch("/markers/<marker_id>", format = "json", data = "<changes>")]
+pub async fn edit_marker(
+    db: &rocket::State<MySqlPool>,
+    mailer: &rocket::State<Mailer>,
+    user_id: UserID,
+    marker_id: u32,
+    changes: Json<MarkerUpdate<'_>>,
+) -> SomsiadResult<FullMarkerOwned> {
+    if let Err(e) = changes.validate() {
+        return SomsiadStatus::errors(invalid_fields(e));
+    }
+    match changes
+        .update_marker(db, mailer, user_id.0, marker_id)
+        .await
+    {
+        Err(e) => match e.downcast_ref::<MarkerError>() {
+            Some(MarkerError::NotFound) => {
+                SomsiadStatus::error("Znacznik nie istnieje lub nie należy do ciebie")
+            }
+            Some(MarkerError::EndBeforeStart) => {
+                SomsiadStatus::error("Wydarzenie nie może kończyć się przed rozpoczęciem")
+            }
+            Some(MarkerError::RecurrenceWithoutStart) => {
+                SomsiadStatus::error("Wydarzenie cykliczne musi mieć czas rozpoczęcia")
+            }
+            Some(MarkerError::UnknownCategory) => {
+                SomsiadStatus::error("Wybrana kategoria nie istnieje")
+            }
+            None => {
+                error_!("Error in edit_marker: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(marker) => SomsiadStatus::ok(marker),
+    }
+}
+
+#[put(
+    "/markers/<marker_id>/attendance",
+    format = "json",
+    data = "<attendance>"
+)]
+pub async fn attend_marker(
+    db: &rocket::State<MySqlPool>,
+    mailer: &rocket::State<Mailer>,
+    user_id: UserID,
+    marker_id: u32,
+    attendance: Json<AttendanceRequest>,
+) -> SomsiadResult<Signup> {
+    match attendance.attend(db, mailer, user_id.0, marker_id).await {
+        Err(e) => match e.downcast_ref::<AttendanceError>() {
+            Some(AttendanceError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            Some(AttendanceError::NotAllowed) => {
+                SomsiadStatus::error("Na to wydarzenie nie można się zapisać")
+            }
+            None => {
+                error_!("Error in attend_marker: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(signup) => SomsiadStatus::ok(signup),
+    }
+}
+
+#[delete("/markers/<marker_id>/attendance")]
+pub async fn leave_marker_attendance(
+    db: &rocket::State<MySqlPool>,
+    mailer: &rocket::State<Mailer>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<()> {
+    match leave_marker(db, mailer, user_id.0, marker_id).await {
+        Ok(true) => SomsiadStatus::ok(()),
+        Ok(false) => SomsiadStatus::error("Nie jesteś zapisany na to wydarzenie"),
+        Err(e) => match e.downcast_ref::<AttendanceError>() {
+            Some(AttendanceError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            _ => {
+                error_!("Error in leave_marker_attendance: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+    }
+}
+
+#[put("/markers/<marker_id>/upvote")]
+pub async fn upvote(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<()> {
+    match upvote_marker(db, user_id.0, marker_id).await {
+        Ok(true) => SomsiadStatus::ok(()),
+        Ok(false) => SomsiadStatus::error("Już oceniłeś ten znacznik"),
+        Err(e) => match e.downcast_ref::<ReputationError>() {
+            Some(ReputationError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            Some(ReputationError::OwnMarker) => {
+                SomsiadStatus::error("Nie możesz ocenić własnego znacznika")
+            }
+            None => {
+                error_!("Error in upvote: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+    }
+}
+
+#[delete("/markers/<marker_id>/upvote")]
+pub async fn withdraw_upvote(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<()> {
+    match remove_upvote(db, user_id.0, marker_id).await {
+        Ok(true) => SomsiadStatus::ok(()),
+        Ok(false) => SomsiadStatus::error("Nie oceniłeś tego znacznika"),
+        Err(e) => match e.downcast_ref::<ReputationError>() {
+            Some(ReputationError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            _ => {
+                error_!("Error in withdraw_upvote: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+    }
+}
+
+async fn move_help_request(
+    db: &MySqlPool,
+    user_id: UserID,
+    marker_id: u32,
+    action: HelpAction,
+) -> SomsiadResult<HelpState> {
+    match action.perform(db, user_id.0, marker_id).await {
+        Err(e) => match e.downcast_ref::<HelpError>() {
+            Some(HelpError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            Some(HelpError::NotHelpRequest) => {
+                SomsiadStatus::error("Ten znacznik nie jest prośbą o pomoc")
+            }
+            Some(HelpError::OwnMarker) => {
+                SomsiadStatus::error("Nie możesz podjąć się własnej prośby o pomoc")
+            }
+            Some(HelpError::NotAllowed) => SomsiadStatus::error("Ta zmiana nie jest teraz możliwa"),
+            None => {
+                error_!("Error while moving help request {}: {}", marker_id, e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(state) => SomsiadStatus::ok(state),
+    }
+}
+
+#[post("/markers/<marker_id>/help/claim")]
+pub async fn claim_help(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<HelpState> {
+    move_help_request(db, user_id, marker_id, HelpAction::Claim).await
+}
+
+#[post("/markers/<marker_id>/help/confirm")]
+pub async fn confirm_help(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<HelpState> {
+    move_help_request(db, user_id, marker_id, HelpAction::Confirm).await
+}
+
+#[post("/markers/<marker_id>/help/done")]
+pub async fn finish_help(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<HelpState> {
+    move_help_request(db, user_id, marker_id, HelpAction::Done).await
+}
+
+#[post("/markers/<marker_id>/help/release")]
+pub async fn release_help(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<HelpState> {
+    move_help_request(db, user_id, marker_id, HelpAction::Release).await
+}
+
+#[get("/markers/<marker_id>/help/history")]
+pub async fn get_help_history(
+    db: &rocket::State<MySqlPool>,
+    marker_id: u32,
+) -> SomsiadResult<Vec<HelpTransition>> {
+    match show_help_history(db, marker_id).await {
+        Err(e) => match e.downcast_ref::<HelpError>() {
+            Some(HelpError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            _ => {
+                error_!("Error in get_help_history: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(transitions) => SomsiadStatus::ok(transitions),
+    }
+}
+
+#[get("/markers/<marker_id>/waitlist", rank = 1)]
+pub async fn get_waitlist_position(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<i64> {
+    match waitlist_position(&**db, user_id.0, marker_id).await {
+        Ok(Some(position)) => SomsiadStatus::ok(position),
+        Ok(None) => SomsiadStatus::error("Nie jesteś na liście oczekujących"),
+        Err(e) => {
+            error_!("Error in get_waitlist_position: {}", e);
+            SomsiadStatus::error("Nieoczekiwany błąd")
+        }
+    }
+}
+
+#[get("/waitlist")]
+pub async fn get_user_waitlist(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+) -> SomsiadResult<Vec<WaitlistEntry>> {
+    match show_user_waitlist(db, user_id.0).await {
+        Ok(entries) => SomsiadStatus::ok(entries),
         Err(e) => {
-            error_!("Error in remove_marker: {}", e);
+            error_!("Error in get_user_waitlist: {}", e);
             SomsiadStatus::error("Nieoczekiwany błąd")
         }
+    }
+}
+
+#[get("/markers/<marker_id>/attendees", rank = 1)]
+pub async fn get_marker_attendees(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<Vec<Attendee>> {
+    match show_attendees(db, user_id.0, marker_id).await {
+        Err(e) => match e.downcast_ref::<AttendanceError>() {
+            Some(AttendanceError::NotFound) => {
+                SomsiadStatus::error("Znacznik nie istnieje lub nie należy do ciebie")
+            }
+            _ => {
+                error_!("Error in get_marker_attendees: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(attendees) => SomsiadStatus::ok(attendees),
+    }
+}
+
+#[get("/markers/<marker_id>/contact", rank = 1)]
+pub async fn get_marker_contact(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<ContactInfo> {
+    let limit = env_or("CONTACT_REVEAL_LIMIT", DEFAULT_REVEAL_LIMIT);
+    match reveal_contact_info(db, user_id.0, marker_id, limit).await {
+        Err(e) => match e.downcast_ref::<ContactError>() {
+            Some(ContactError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            Some(ContactError::RateLimited) => SomsiadStatus::error(
+                "Wyświetliłeś zbyt wiele danych kontaktowych, spróbuj ponownie później",
+            ),
+            None => {
+                error_!("Error in get_marker_contact: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(contact_info) => SomsiadStatus::ok(contact_info),
+    }
+}
+
+#[get("/markers/<marker_id>/contact/views")]
+pub async fn get_marker_contact_views(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+) -> SomsiadResult<Vec<ContactView>> {
+    match show_contact_views(db, user_id.0, marker_id).await {
+        Err(e) => match e.downcast_ref::<ContactError>() {
+            Some(ContactError::NotFound) => {
+                SomsiadStatus::error("Znacznik nie istnieje lub nie należy do ciebie")
+            }
+            _ => {
+                error_!("Error in get_marker_contact_views: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(views) => SomsiadStatus::ok(views),
+    }
+}
+
+#[get("/markers/<marker_id>/history", rank = 1)]
+pub async fn get_marker_history(
+    db: &rocket::State<MySqlPool>,
+    user_id: Option<UserID>,
+    marker_id: u32,
+) -> SomsiadResult<Vec<Revision>> {
+    // Authors and moderators see what the contact info was changed from and to
+    let full = match user_id {
+        Some(user_id) => {
+            let author = is_author(db, user_id.0, marker_id).await;
+            let moderator = has_role(db, user_id.0, Role::Moderator).await;
+            match (author, moderator) {
+                (Ok(author), Ok(moderator)) => author || moderator,
+                (Err(e), _) | (_, Err(e)) => {
+                    error_!("Error in get_marker_history: {}", e);
+                    return SomsiadStatus::error("Nieoczekiwany błąd");
+                }
+            }
+        }
+        None => false,
+    };
+    match show_history(db, marker_id, full).await {
+        Err(e) => match e.downcast_ref::<RevisionError>() {
+            Some(RevisionError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            None => {
+                error_!("Error in get_marker_history: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(revisions) => SomsiadStatus::ok(revisions),
+    }
+}
+
+#[get("/markers/<marker_id>/comments?<cursor>&<limit>")]
+pub async fn get_marker_comments(
+    db: &rocket::State<MySqlPool>,
+    user_id: Option<UserID>,
+    marker_id: u32,
+    cursor: Option<&str>,
+    limit: Option<u32>,
+) -> Result<Page<Thread>, SomsiadResult<()>> {
+    let page = match PageRequest::new(cursor, limit) {
+        Some(page) => page,
+        None => return Err(SomsiadStatus::error("Nieprawidłowy kursor")),
+    };
+    match show_comments(db, user_id.map(|id| id.0), marker_id, page).await {
+        Ok(comments) => Ok(comments),
+        Err(e) => match e.downcast_ref::<CommentError>() {
+            Some(CommentError::MarkerNotFound) => {
+                Err(SomsiadStatus::error("Znacznik nie istnieje"))
+            }
+            _ => {
+                error_!("Error in get_marker_comments: {}", e);
+                Err(SomsiadStatus::error("Wewnętrzny błąd serwera"))
+            }
+        },
+    }
+}
+
+#[post("/markers/<marker_id>/comments", format = "json", data = "<comment>")]
+pub async fn add_marker_comment(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+    comment: Json<NewComment<'_>>,
+) -> SomsiadResult<u64> {
+    if let Err(e) = comment.validate() {
+        return SomsiadStatus::errors(invalid_fields(e));
+    }
+    match comment.add_comment(db, user_id.0, marker_id).await {
+        Err(e) => match e.downcast_ref::<CommentError>() {
+            Some(CommentError::MarkerNotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            Some(CommentError::InvalidParent) => {
+                SomsiadStatus::error("Można odpowiadać tylko na komentarze pod tym znacznikiem")
+            }
+            _ => {
+                error_!("Error in add_marker_comment: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(id) => SomsiadStatus::ok(id),
+    }
+}
+
+#[delete("/comments/<comment_id>")]
+pub async fn remove_comment(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    comment_id: u32,
+) -> SomsiadResult<()> {
+    match delete_comment(db, user_id.0, comment_id).await {
+        Err(e) => match e.downcast_ref::<CommentError>() {
+            Some(CommentError::NotFound) => {
+                SomsiadStatus::error("Komentarz nie istnieje lub nie możesz go usunąć")
+            }
+            _ => {
+                error_!("Error in remove_comment: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(()) => SomsiadStatus::ok(()),
+    }
+}
+
+#[post("/markers/<marker_id>/images", data = "<upload>")]
+pub async fn add_marker_image(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+    mut upload: Form<ImageUpload<'_>>,
+) -> SomsiadResult<ImageFile> {
+    match upload.add_image(db, user_id.0, marker_id).await {
+        Err(e) => match e.downcast_ref::<ImageError>() {
+            Some(ImageError::NotFound) => {
+                SomsiadStatus::error("Znacznik nie istnieje lub nie należy do ciebie")
+            }
+            Some(ImageError::TooLarge) => SomsiadStatus::error(&format!(
+                "Zdjęcie może mieć najwyżej {} MiB",
+                MAX_IMAGE_SIZE / 1024 / 1024
+            )),
+            Some(ImageError::TooMany) => {
+                SomsiadStatus::error("Osiągnięto limit zdjęć dla tego znacznika")
+            }
+            Some(ImageError::Unsupported) => {
+                SomsiadStatus::error("Obsługiwane są tylko zdjęcia JPEG, PNG, WebP i GIF")
+            }
+            None => {
+                error_!("Error in add_marker_image: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(image) => SomsiadStatus::ok(image),
+    }
+}
+
+#[delete("/images/<image_id>")]
+pub async fn remove_marker_image(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    image_id: u32,
+) -> SomsiadResult<()> {
+    match delete_image(db, user_id.0, image_id).await {
+        Err(e) => match e.downcast_ref::<ImageError>() {
+            Some(ImageError::NotFound) => {
+                SomsiadStatus::error("Zdjęcie nie istnieje lub nie należy do ciebie")
+            }
+            _ => {
+                error_!("Error in remove_marker_image: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(()) => SomsiadStatus::ok(()),
+    }
+}
+
+/// Mounted under `UPLOADS_URL`, files of markers the viewer can't see aren't found
+#[get("/markers/<marker_id>/<file>")]
+pub async fn get_marker_image_file(
+    db: &rocket::State<MySqlPool>,
+    user_id: Option<UserID>,
+    marker_id: u32,
+    file: &str,
+) -> Option<NamedFile> {
+    match find_image_file(db, user_id.map(|id| id.0), marker_id, file).await {
+        Err(e) => {
+            if e.downcast_ref::<ImageError>().is_none() {
+                error_!("Error in get_marker_image_file: {}", e);
+            }
+            None
+        }
+        Ok(path) => NamedFile::open(path).await.ok(),
+    }
+}
+
+#[get("/categories")]
+pub async fn get_categories(db: &rocket::State<MySqlPool>) -> SomsiadResult<Vec<Category>> {
+    match show_categories(db).await {
+        Ok(categories) => SomsiadStatus::ok(categories),
+        Err(e) => {
+            error_!("Error: {}", e);
+            SomsiadStatus::error("Wewnętrzny błąd serwera")
+        }
+    }
+}
+
+fn category_error(e: anyhow::Error) -> &'static str {
+    match e.downcast_ref::<CategoryError>() {
+        Some(CategoryError::NotFound) => "Kategoria nie istnieje",
+        Some(CategoryError::SlugTaken) => "Kategoria o tym identyfikatorze już istnieje",
+        Some(CategoryError::InvalidParent) => {
+            "Kategoria nadrzędna musi istnieć i nie może mieć własnej kategorii nadrzędnej"
+        }
+        Some(CategoryError::InUse) => {
+            "Kategoria ma jeszcze znaczniki lub podkategorie, przenieś je najpierw"
+        }
+        None => {
+            error_!("Error while managing categories: {}", e);
+            "Nieoczekiwany błąd"
+        }
+    }
+}
+
+#[post("/admin/categories", format = "json", data = "<category>")]
+pub async fn add_category(
+    db: &rocket::State<MySqlPool>,
+    _admin: AdminID,
+    category: Json<NewCategory<'_>>,
+) -> SomsiadResult<u64> {
+    if let Err(e) = category.validate() {
+        return SomsiadStatus::errors(invalid_fields(e));
+    }
+    match category.add_category(db).await {
+        Ok(id) => SomsiadStatus::ok(id),
+        Err(e) => SomsiadStatus::error(category_error(e)),
+    }
+}
+
+#[patch("/admin/categories/<category_id>", format = "json", data = "<changes>")]
+pub async fn edit_category(
+    db: &rocket::State<MySqlPool>,
+    _admin: AdminID,
+    category_id: u32,
+    changes: Json<CategoryUpdate<'_>>,
+) -> SomsiadResult<()> {
+    if let Err(e) = changes.validate() {
+        return SomsiadStatus::errors(invalid_fields(e));
+    }
+    match changes.update_category(db, category_id).await {
+        Ok(()) => SomsiadStatus::ok(()),
+        Err(e) => SomsiadStatus::error(category_error(e)),
+    }
+}
+
+#[delete("/admin/categories/<category_id>")]
+pub async fn remove_category(
+    db: &rocket::State<MySqlPool>,
+    _admin: AdminID,
+    category_id: u32,
+) -> SomsiadResult<()> {
+    match delete_category(db, category_id).await {
+        Ok(()) => SomsiadStatus::ok(()),
+        Err(e) => SomsiadStatus::error(category_error(e)),
+    }
+}
+
+#[post("/markers/<marker_id>/reports", format = "json", data = "<report>")]
+pub async fn report_marker(
+    db: &rocket::State<MySqlPool>,
+    user_id: UserID,
+    marker_id: u32,
+    report: Json<Report<'_>>,
+) -> SomsiadResult<()> {
+    if let Err(e) = report.validate() {
+        return SomsiadStatus::errors(invalid_fields(e));
+    }
+    let auto_hide_reports = env_or("AUTO_HIDE_REPORTS", DEFAULT_AUTO_HIDE_REPORTS);
+    match report
+        .report_marker(db, user_id.0, marker_id, auto_hide_reports)
+        .await
+    {
+        Err(e) => match e.downcast_ref::<ModerationError>() {
+            Some(ModerationError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            Some(ModerationError::OwnMarker) => {
+                SomsiadStatus::error("Nie możesz zgłosić własnego znacznika")
+            }
+            Some(ModerationError::AlreadyReported) => {
+                SomsiadStatus::error("Twoje zgłoszenie tego znacznika czeka już na moderatora")
+            }
+            None => {
+                error_!("Error in report_marker: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(()) => SomsiadStatus::ok(()),
+    }
+}
+
+#[get("/moderation/reports?<limit>")]
+pub async fn get_report_queue(
+    db: &rocket::State<MySqlPool>,
+    _moderator: ModeratorID,
+    limit: Option<u32>,
+) -> SomsiadResult<Vec<ReportedMarker>> {
+    match show_report_queue(db, limit).await {
+        Ok(markers) => SomsiadStatus::ok(markers),
+        Err(e) => {
+            error_!("Error: {}", e);
+            SomsiadStatus::error("Wewnętrzny błąd serwera")
+        }
+    }
+}
+
+async fn moderate_visibility(db: &MySqlPool, marker_id: u32, hidden: bool) -> SomsiadResult<()> {
+    match set_hidden(db, marker_id, hidden).await {
+        Err(e) => match e.downcast_ref::<ModerationError>() {
+            Some(ModerationError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            _ => {
+                error_!("Error while moderating marker {}: {}", marker_id, e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(()) => SomsiadStatus::ok(()),
+    }
+}
+
+#[post("/moderation/markers/<marker_id>/hide")]
+pub async fn hide_marker(
+    db: &rocket::State<MySqlPool>,
+    _moderator: ModeratorID,
+    marker_id: u32,
+) -> SomsiadResult<()> {
+    moderate_visibility(db, marker_id, true).await
+}
+
+#[post("/moderation/markers/<marker_id>/restore")]
+pub async fn restore_marker(
+    db: &rocket::State<MySqlPool>,
+    _moderator: ModeratorID,
+    marker_id: u32,
+) -> SomsiadResult<()> {
+    moderate_visibility(db, marker_id, false).await
+}
+
+#[delete("/moderation/markers/<marker_id>")]
+pub async fn moderator_remove_marker(
+    db: &rocket::State<MySqlPool>,
+    _moderator: ModeratorID,
+    marker_id: u32,
+) -> SomsiadResult<FullMarkerOwned> {
+    match delete_marker_as_moderator(db, marker_id).await {
+        Err(e) => match e.downcast_ref::<MarkerError>() {
+            Some(MarkerError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            _ => {
+                error_!("Error in moderator_remove_marker: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
         Ok(marker) => SomsiadStatus::ok(marker),
     }
 }
 
+#[post("/moderation/markers/<marker_id>/revisions/<revision_id>/rollback")]
+pub async fn rollback_marker_revision(
+    db: &rocket::State<MySqlPool>,
+    mailer: &rocket::State<Mailer>,
+    moderator: ModeratorID,
+    marker_id: u32,
+    revision_id: u32,
+) -> SomsiadResult<FullMarkerOwned> {
+    match rollback_revision(db, mailer, moderator.0, marker_id, revision_id).await {
+        Err(e) => {
+            if let Some(RevisionError::NotFound) = e.downcast_ref::<RevisionError>() {
+                return SomsiadStatus::error("Znacznik lub jego wersja nie istnieje");
+            }
+            match e.downcast_ref::<MarkerError>() {
+                Some(MarkerError::UnknownCategory) => {
+                    SomsiadStatus::error("Kategoria z tej wersji znacznika już nie istnieje")
+                }
+                Some(MarkerError::EndBeforeStart | MarkerError::RecurrenceWithoutStart) => {
+                    SomsiadStatus::error("Przywrócone czasy wydarzenia byłyby nieprawidłowe")
+                }
+                _ => {
+                    error_!("Error in rollback_marker_revision: {}", e);
+                    SomsiadStatus::error("Nieoczekiwany błąd")
+                }
+            }
+        }
+        Ok(marker) => SomsiadStatus::ok(marker),
+    }
+}
+
+#[post(
+    "/moderation/markers/<marker_id>/warn",
+    format = "json",
+    data = "<warning>"
+)]
+pub async fn warn_marker_author(
+    db: &rocket::State<MySqlPool>,
+    mailer: &rocket::State<Mailer>,
+    moderator: ModeratorID,
+    marker_id: u32,
+    warning: Json<Warning<'_>>,
+) -> SomsiadResult<()> {
+    if let Err(e) = warning.validate() {
+        return SomsiadStatus::errors(invalid_fields(e));
+    }
+    match warning
+        .warn_author(db, mailer, moderator.0, marker_id)
+        .await
+    {
+        Err(e) => match e.downcast_ref::<ModerationError>() {
+            Some(ModerationError::NotFound) => SomsiadStatus::error("Znacznik nie istnieje"),
+            _ => {
+                error_!("Error in warn_marker_author: {}", e);
+                SomsiadStatus::error("Nieoczekiwany błąd")
+            }
+        },
+        Ok(()) => SomsiadStatus::ok(()),
+    }
+}
+
 #[post("/register", format = "json", data = "<user>")]
 pub async fn register(
     db: &rocket::State<MySqlPool>,
     user: Json<UserRegister<'_>>,
 ) -> SomsiadResult<()> {
     if let Err(e) = user.validate() {
-        return SomsiadStatus::errors(
-            e.errors()
-                .iter()
-                .map(|(field, err_kinds)| match err_kinds {
-                    Struct(err) => err
-                        .errors()
-                        .iter()
-                        .map(|(field, _)| field.to_string())
-                        .collect(),
-                    _ => field.to_string(),
-                })
-                .collect(),
-        );
+        return SomsiadStatus::errors(flat_invalid_fields(e));
     }
     match user.add_to_db(db).await {
         Err(e) => match e.to_string().split(' ').last().unwrap_or_default() {
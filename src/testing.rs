@@ -0,0 +1,83 @@
+//! Helpers of the tests running against a database, see `#[sqlx::test]`
+use serde::Serialize;
+use sqlx::{Executor, MySqlPool};
+
+use crate::markers::Page;
+
+const ADDRESS: &str = r#"{"street": "Jagiellońska", "number": "13", "city": "Sosnowiec"}"#;
+const CONTACT_INFO: &str = r#"{
+    "name": "Jan", "surname": "Kowalski",
+    "address": {"street": "Jagiellońska", "number": "13", "city": "Sosnowiec"},
+    "method": {"type": "Email", "val": "jan@example.com"}
+}"#;
+
+/// Creates the tables of `sql/structure.sql` in the database of the test.
+/// The script creates and selects a database of its own, those lines are left out
+pub async fn create_schema(db: &MySqlPool) -> anyhow::Result<()> {
+    let schema = include_str!("../sql/structure.sql")
+        .lines()
+        .filter(|line| !line.starts_with("CREATE DATABASE") && !line.starts_with("USE "))
+        .collect::<Vec<_>>()
+        .join("\n");
+    db.execute(schema.as_str()).await?;
+
+    Ok(())
+}
+
+/// Adds a user called `name`, returns their id
+pub async fn add_user(db: &MySqlPool, name: &str) -> anyhow::Result<u32> {
+    let added = sqlx::query("INSERT INTO users (email, name, password) VALUES (?, ?, '')")
+        .bind(format!("{}@example.com", name))
+        .bind(name)
+        .execute(db)
+        .await?;
+    let id = added.last_insert_id() as u32;
+
+    sqlx::query(
+        r#"
+        INSERT INTO full_users_info (id, name, surname, sex, address, reputation)
+        VALUES (?, ?, "Kowalski", "O", ?, 0)
+        "#,
+    )
+    .bind(id)
+    .bind(name)
+    .bind(ADDRESS)
+    .execute(db)
+    .await?;
+
+    Ok(id)
+}
+
+/// Adds a neighbour help request of `user_id` in Sosnowiec, returns its id.
+/// Tests change the other columns with an `UPDATE` when they need to
+pub async fn add_marker(db: &MySqlPool, user_id: u32, title: &str) -> anyhow::Result<u32> {
+    let added = sqlx::query(
+        r#"
+        INSERT INTO markers
+        (latitude, longitude, title, description, category_id, address, contact_info, user_id)
+        VALUES (50.28, 19.13, ?, "", 1, ?, ?, ?)
+        "#,
+    )
+    .bind(title)
+    .bind(ADDRESS)
+    .bind(CONTACT_INFO)
+    .bind(user_id)
+    .execute(db)
+    .await?;
+
+    Ok(added.last_insert_id() as u32)
+}
+
+/// Ids of the items on the page, in order
+pub fn ids<T: Serialize>(page: Page<T>) -> anyhow::Result<Vec<u64>> {
+    let (items, _) = page.into_parts();
+    let items = serde_json::to_value(items)?;
+    let ids = items
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item["id"].as_u64())
+        .collect();
+
+    Ok(ids)
+}
@@ -1,5 +1,6 @@
 use bcrypt::verify;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use validator::Validate;
 
 use super::register::Sex;
@@ -11,13 +12,22 @@ pub struct UserLogin<'r> {
     pub password: &'r str,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Validate)]
 pub struct AddressOwned {
+    #[validate(length(min = 1))]
     street: String,
+    #[validate(length(min = 1))]
     number: String,
+    #[validate(length(min = 1))]
     city: String,
 }
 
+impl fmt::Display for AddressOwned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}, {}", self.street, self.number, self.city)
+    }
+}
+
 #[derive(Serialize)]
 pub struct UserPrivateInfo {
     username: String,
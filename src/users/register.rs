@@ -15,14 +15,15 @@ pub struct UserRegister<'r> {
     sex: Sex,
     #[validate]
     address: Address<'r>,
-    #[serde(default)]
-    reputation: u32,
 }
 
 #[derive(Deserialize, Serialize, Validate)]
 pub struct Address<'r> {
+    #[validate(length(min = 1))]
     street: &'r str,
+    #[validate(length(min = 1))]
     number: &'r str,
+    #[validate(length(min = 1))]
     city: &'r str,
 }
 
@@ -55,15 +56,15 @@ impl UserRegister<'_> {
         let last_insert_id = user_insert.last_insert_id();
 
         let full_user_insert = sqlx::query!(
-            "insert into full_users_info (id,name,surname,sex,address,reputation) values(?,?,?,?,?,?);",
+            "insert into full_users_info (id,name,surname,sex,address) values(?,?,?,?,?);",
             last_insert_id,
             self.name,
             self.surname,
             self.sex,
-            serde_json::to_string(&self.address)?,
-            self.reputation)
-            .execute(&mut tx)
-            .await?;
+            serde_json::to_string(&self.address)?
+        )
+        .execute(&mut tx)
+        .await?;
 
         tx.commit().await?;
 
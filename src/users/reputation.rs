@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ReputationError {
+    NotFound,
+    OwnMarker,
+}
+
+impl fmt::Display for ReputationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "marker not found"),
+            Self::OwnMarker => write!(f, "users can't upvote their own markers"),
+        }
+    }
+}
+
+impl std::error::Error for ReputationError {}
+
+/// Why the reputation of a user changed, every change is kept in the `reputation_events` ledger
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum ReputationReason {
+    /// Neighbour help the user gave was completed
+    Help,
+    /// Someone upvoted a marker of the user
+    Upvote,
+    /// Someone took back their upvote, offsets the entry of the upvote
+    Unvote,
+    /// A moderator warned the user
+    Penalty,
+}
+
+impl ReputationReason {
+    pub fn points(self) -> i32 {
+        match self {
+            Self::Help => 10,
+            Self::Upvote => 1,
+            Self::Unvote => -1,
+            Self::Penalty => -20,
+        }
+    }
+}
+
+/// Adds an entry to the ledger and updates the reputation derived from it. Run it in the same
+/// transaction as whatever caused the change
+pub async fn record(
+    tx: &mut sqlx::Transaction<'_, sqlx::MySql>,
+    user_id: u32,
+    reason: ReputationReason,
+    marker_id: Option<u32>,
+    source_user_id: Option<u32>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO reputation_events (user_id, reason, points, marker_id, source_user_id)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+        user_id,
+        reason,
+        reason.points(),
+        marker_id,
+        source_user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    refresh(tx, user_id).await
+}
+
+/// Recomputes the reputation of the user from the ledger
+async fn refresh(tx: &mut sqlx::Transaction<'_, sqlx::MySql>, user_id: u32) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE full_users_info
+        SET reputation = (SELECT COALESCE(SUM(points), 0) FROM reputation_events WHERE user_id = ?)
+        WHERE id = ?
+        "#,
+        user_id,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Upvotes the marker, its author gains reputation. Returns false if it was already upvoted
+pub async fn upvote_marker(
+    db: &sqlx::MySqlPool,
+    user_id: u32,
+    marker_id: u32,
+) -> anyhow::Result<bool> {
+    let mut tx = db.begin().await?;
+
+    let marker = sqlx::query!(
+        r#"
+        SELECT user_id FROM markers
+        WHERE id = ? AND hidden_at IS NULL AND deleted_at IS NULL AND NOT draft
+        "#,
+        marker_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(ReputationError::NotFound)?;
+    let author = marker.user_id as u32;
+    if author == user_id {
+        return Err(ReputationError::OwnMarker.into());
+    }
+
+    let voted = sqlx::query!(
+        "INSERT IGNORE INTO marker_votes (marker_id, user_id) VALUES (?, ?)",
+        marker_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+    if voted.rows_affected() == 0 {
+        return Ok(false);
+    }
+    record(
+        &mut tx,
+        author,
+        ReputationReason::Upvote,
+        Some(marker_id),
+        Some(user_id),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// Takes the upvote back together with the reputation it gave. Returns false if there was none
+pub async fn remove_upvote(
+    db: &sqlx::MySqlPool,
+    user_id: u32,
+    marker_id: u32,
+) -> anyhow::Result<bool> {
+    let mut tx = db.begin().await?;
+
+    let marker = sqlx::query!("SELECT user_id FROM markers WHERE id = ?", marker_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(ReputationError::NotFound)?;
+
+    let removed = sqlx::query!(
+        "DELETE FROM marker_votes WHERE marker_id = ? AND user_id = ?",
+        marker_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+    if removed.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    record(
+        &mut tx,
+        marker.user_id as u32,
+        ReputationReason::Unvote,
+        Some(marker_id),
+        Some(user_id),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use sqlx::MySqlPool;
+
+    async fn reputation(db: &MySqlPool, user_id: u32) -> anyhow::Result<i32> {
+        let reputation = sqlx::query_scalar("SELECT reputation FROM full_users_info WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(db)
+            .await?;
+
+        Ok(reputation)
+    }
+
+    #[sqlx::test]
+    async fn upvotes_go_through_the_ledger(db: MySqlPool) -> anyhow::Result<()> {
+        testing::create_schema(&db).await?;
+        let jan = testing::add_user(&db, "jan").await?;
+        let anna = testing::add_user(&db, "anna").await?;
+        let piotr = testing::add_user(&db, "piotr").await?;
+        let marker = testing::add_marker(&db, jan, "Zakupy").await?;
+
+        let own = upvote_marker(&db, jan, marker).await;
+        let own = own.err().and_then(|e| e.downcast::<ReputationError>().ok());
+        assert!(matches!(own, Some(ReputationError::OwnMarker)));
+        assert!(upvote_marker(&db, anna, marker).await?);
+        assert!(!upvote_marker(&db, anna, marker).await?);
+        assert!(upvote_marker(&db, piotr, marker).await?);
+        assert_eq!(reputation(&db, jan).await?, 2);
+
+        assert!(remove_upvote(&db, anna, marker).await?);
+        assert!(!remove_upvote(&db, anna, marker).await?);
+        assert_eq!(reputation(&db, jan).await?, 1);
+        assert!(upvote_marker(&db, anna, marker).await?);
+        assert_eq!(reputation(&db, jan).await?, 2);
+
+        // Withdrawn upvotes stay in the ledger, offset by an entry of their own
+        let points: Vec<i32> = sqlx::query_scalar(
+            "SELECT points FROM reputation_events WHERE user_id = ? ORDER BY id",
+        )
+        .bind(jan)
+        .fetch_all(&db)
+        .await?;
+        assert_eq!(points, [1, 1, -1, 1]);
+
+        Ok(())
+    }
+}